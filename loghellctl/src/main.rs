@@ -1,4 +1,4 @@
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use clap::{Args, Parser, Subcommand};
 use hyper::{Body, Client, Method, Request, StatusCode};
@@ -29,7 +29,44 @@ enum Commands {
     /// Simulate sending logs to Loghell
     Simulate,
     /// Subscribe for new logs
-    Subscribe,
+    Subscribe(SubscribeArgs),
+    /// Delete logs matched by the query
+    Delete(DeleteArgs),
+    /// Measure throughput of concurrent ingestion and search
+    Bench(BenchArgs),
+}
+
+#[derive(Debug, Args)]
+struct SubscribeArgs {
+    /// Query to filter logs
+    #[clap(short, long, default_value = "level:debug")]
+    query: String,
+}
+
+#[derive(Debug, Args)]
+struct DeleteArgs {
+    /// Query to filter logs
+    #[clap(short, long)]
+    query: String,
+    /// Only count logs which would be deleted
+    #[clap(long)]
+    dry_run: bool,
+}
+
+#[derive(Debug, Args)]
+struct BenchArgs {
+    /// Number of connections sending logs
+    #[clap(short, long, default_value_t = 4)]
+    writers: usize,
+    /// Number of clients searching logs
+    #[clap(short, long, default_value_t = 4)]
+    readers: usize,
+    /// Benchmark duration in seconds
+    #[clap(short, long, default_value_t = 10)]
+    duration: u64,
+    /// Query to search logs
+    #[clap(short, long, default_value = "level:debug")]
+    query: String,
 }
 
 #[tokio::main]
@@ -49,7 +86,9 @@ async fn do_main() -> Result<(), Box<dyn std::error::Error>> {
     match cli.command {
         Commands::Health => health(&endpoint).await?,
         Commands::Simulate => simulation(&endpoint).await?,
-        Commands::Subscribe => subscribe(&endpoint).await?,
+        Commands::Subscribe(args) => subscribe(&endpoint, &args.query).await?,
+        Commands::Delete(args) => delete(&endpoint, &args.query, args.dry_run).await?,
+        Commands::Bench(args) => bench(&endpoint, &args).await?,
     }
     Ok(())
 }
@@ -73,33 +112,180 @@ async fn simulation(endpoint: &str) -> Result<(), Box<dyn std::error::Error>> {
         data.push_str(r#"{"level":"debug","component":"example","time":""#);
         data.push_str(&now_as_nanos_u64()?.to_string());
         data.push_str(r#"","message":"example debug log"}"#);
+        data.push('\n');
         stream.write_all(data.as_bytes()).await?;
         tokio::time::sleep(Duration::from_millis(1000)).await;
     }
     Ok(())
 }
 
-async fn subscribe(endpoint: &str) -> Result<(), Box<dyn std::error::Error>> {
+async fn subscribe(endpoint: &str, query: &str) -> Result<(), Box<dyn std::error::Error>> {
     let mut stream = TcpStream::connect(endpoint).await?;
-    stream.write_all(b"GET /events HTTP/1.1").await?;
+    let request =
+        format!("GET /events?q={} HTTP/1.1\r\nHost: {}\r\n\r\n", percent_encode(query), endpoint);
+    stream.write_all(request.as_bytes()).await?;
     let mut reader = BufReader::new(stream);
+    let mut status = String::new();
+    reader.read_line(&mut status).await?;
+    if !status.starts_with("HTTP/1.1 200") {
+        return Err(format!("incorrect response status: {}", status.trim_end()).into());
+    }
     loop {
         let mut buf: String = String::new();
         reader.read_line(&mut buf).await?;
         if buf.is_empty() {
             break;
         }
-        // Delete new line.
-        buf.pop();
-        // It means json log.
-        // We need to skip other messages to not show SSE HTTP responsa and check messages.
-        if buf.starts_with('{') {
-            eprintln!("{}", buf);
+        // We need to skip other lines to not show SSE HTTP response headers, ids and pings.
+        if let Some(data) = buf.strip_prefix("data: ") {
+            eprintln!("{}", data.trim_end());
         }
     }
     Ok(())
 }
 
+async fn delete(
+    endpoint: &str,
+    query: &str,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let client = Client::new();
+    let uri =
+        format!("http://{}/api/logs?q={}&dry_run={}", endpoint, percent_encode(query), dry_run);
+    let req = Request::builder().method(Method::DELETE).uri(uri).body(Body::empty())?;
+    let res = client.request(req).await.map_err(|e| format!("failed to send request: {}", e))?;
+    let status = res.status().as_u16();
+    let body = hyper::body::to_bytes(res.into_body()).await?;
+    let body = String::from_utf8_lossy(&body);
+    if status != StatusCode::OK {
+        return Err(format!("incorrect response status code: {}: {}", status, body).into());
+    }
+    println!("{}", body);
+    Ok(())
+}
+
+async fn bench(endpoint: &str, args: &BenchArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let records = stored_records(endpoint).await?;
+    let start = Instant::now();
+    let deadline = start + Duration::from_secs(args.duration);
+    let mut writers = Vec::with_capacity(args.writers);
+    for id in 0..args.writers {
+        writers.push(tokio::spawn(bench_writer(endpoint.to_string(), id, deadline)));
+    }
+    let mut readers = Vec::with_capacity(args.readers);
+    for _ in 0..args.readers {
+        readers.push(tokio::spawn(bench_reader(
+            endpoint.to_string(),
+            args.query.clone(),
+            deadline,
+        )));
+    }
+    let mut sent = 0;
+    for writer in writers {
+        sent += writer.await?.map_err(|e| e.to_string())?;
+    }
+    let mut latencies: Vec<Duration> = Vec::new();
+    for reader in readers {
+        latencies.extend(reader.await?.map_err(|e| e.to_string())?);
+    }
+    // Logs are stored asynchronously, so we wait until all sent logs are stored.
+    let mut stored = stored_records(endpoint).await?.saturating_sub(records);
+    while stored < sent && start.elapsed() < Duration::from_secs(args.duration * 2) {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        stored = stored_records(endpoint).await?.saturating_sub(records);
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    println!(
+        "ingest: {} logs sent, {} stored in {:.1}s ({:.0} logs/s)",
+        sent,
+        stored,
+        elapsed,
+        stored as f64 / elapsed
+    );
+    let searches = latencies.len();
+    latencies.sort_unstable();
+    let percentile = |p: usize| match searches {
+        0 => Duration::ZERO,
+        n => latencies[(n - 1) * p / 100],
+    };
+    println!(
+        "search: {} requests ({:.0} requests/s), latency p50 {:?}, p99 {:?}, max {:?}",
+        searches,
+        searches as f64 / args.duration as f64,
+        percentile(50),
+        percentile(99),
+        percentile(100)
+    );
+    Ok(())
+}
+
+// Returns the number of sent logs.
+async fn bench_writer(
+    endpoint: String,
+    id: usize,
+    deadline: Instant,
+) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+    let mut stream = TcpStream::connect(endpoint).await?;
+    let mut sent = 0;
+    while Instant::now() < deadline {
+        // Logs are sent in batches to not measure the overhead of small writes.
+        let mut data = String::new();
+        for _ in 0..100 {
+            data.push_str(&format!(
+                r#"{{"level":"debug","component":"bench","writer":{},"id":{},"message":"bench log"}}"#,
+                id, sent
+            ));
+            data.push('\n');
+            sent += 1;
+        }
+        stream.write_all(data.as_bytes()).await?;
+    }
+    Ok(sent)
+}
+
+// Returns latencies of all searches.
+async fn bench_reader(
+    endpoint: String,
+    query: String,
+    deadline: Instant,
+) -> Result<Vec<Duration>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = Client::new();
+    let uri = format!("http://{}/api/search?q={}&limit=100", endpoint, percent_encode(&query));
+    let mut latencies = Vec::new();
+    while Instant::now() < deadline {
+        let start = Instant::now();
+        let res = client.get(uri.parse()?).await?;
+        let status = res.status();
+        hyper::body::to_bytes(res.into_body()).await?;
+        if status != StatusCode::OK {
+            return Err(format!("incorrect response status code: {}", status.as_u16()).into());
+        }
+        latencies.push(start.elapsed());
+    }
+    Ok(latencies)
+}
+
+async fn stored_records(endpoint: &str) -> Result<u64, Box<dyn std::error::Error>> {
+    let client = Client::new();
+    let res = client.get(format!("http://{}/api/stats", endpoint).parse()?).await?;
+    let body = hyper::body::to_bytes(res.into_body()).await?;
+    let stats: serde_json::Value = serde_json::from_slice(&body)?;
+    stats["records"].as_u64().ok_or_else(|| "records are missing in stats".into())
+}
+
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
 fn now_as_nanos_u64() -> Result<u64, Box<dyn std::error::Error>> {
     let now_as_nanos_u128 = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
     let now_as_nanos_u64 = u64::try_from(now_as_nanos_u128)?;
@@ -0,0 +1,55 @@
+use tokio::sync::watch;
+use tracing::{debug, trace};
+
+use crate::{config::Config, log_storage::LogStoragePointer};
+
+/// Saves index checkpoints periodically and on shutdown,
+/// so only logs stored after the last checkpoint are indexed on restart.
+pub(crate) struct Checkpointer {
+    enabled: bool,
+    interval: tokio::time::Duration,
+}
+
+impl Checkpointer {
+    pub(crate) fn new(cfg: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        if cfg.index_checkpoint_interval == 0 {
+            return Err("index checkpoint interval should be greater than zero".into());
+        }
+        Ok(Self {
+            enabled: !cfg.index_path.is_empty(),
+            interval: tokio::time::Duration::from_secs(cfg.index_checkpoint_interval),
+        })
+    }
+
+    pub(crate) async fn start(
+        &self,
+        log_storage: LogStoragePointer,
+        mut shutdown_rx: watch::Receiver<()>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.enabled {
+            debug!("index checkpoints are disabled");
+            return Ok(());
+        }
+        let mut interval = tokio::time::interval(self.interval);
+        // The first tick is completed immediately, but index was just restored.
+        interval.tick().await;
+        loop {
+            tokio::select! {
+                _ = interval.tick() => self.checkpoint(&log_storage).await?,
+                _ = shutdown_rx.changed() => {
+                    debug!("received shutdown signal; save the last checkpoint");
+                    return self.checkpoint(&log_storage).await;
+                }
+            }
+        }
+    }
+
+    async fn checkpoint(
+        &self,
+        log_storage: &LogStoragePointer,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        log_storage.checkpoint().await?;
+        trace!("index checkpoint is saved");
+        Ok(())
+    }
+}
@@ -1,21 +1,41 @@
-use std::sync::Arc;
-
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
     net::TcpStream,
+    sync::broadcast::error::RecvError,
 };
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
 
 use crate::{
-    log_storage::{LogStoragePointer, Notifier},
+    log_storage::{Batch, Key, LogStoragePointer, Notifier},
     server, shared,
 };
 
 pub(crate) const NEW_LOG_MESSAGE_TYPE: u8 = 1;
+// Key is sent as fixed length hex string.
+const KEY_LENGTH: usize = 16;
 
 #[derive(Debug, Clone)]
 pub(crate) enum Message {
-    NewLog(Arc<Vec<u8>>),
+    NewLogs(Batch),
+}
+
+impl Message {
+    /// Encodes every log as message type, log key, log data and new line.
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        match self {
+            Message::NewLogs(records) => {
+                let size: usize = records.iter().map(|x| 1 + KEY_LENGTH + x.data.len() + 1).sum();
+                let mut data: Vec<u8> = Vec::with_capacity(size);
+                for record in records.iter() {
+                    data.push(NEW_LOG_MESSAGE_TYPE);
+                    data.extend_from_slice(format!("{:016x}", record.hit.key).as_bytes());
+                    data.extend_from_slice(&record.data);
+                    data.push(10); // add new line
+                }
+                data
+            }
+        }
+    }
 }
 
 pub(crate) type Transmitter = tokio::sync::broadcast::Sender<Message>;
@@ -53,10 +73,14 @@ impl Cluster {
         }
         loop {
             tokio::select! {
-                new_log = lsn.recv() => {
-                    let new_log = new_log?;
-                    shared::broadcast(&self.cst, Message::NewLog(new_log))?;
-                }
+                new_logs = lsn.recv() => match new_logs {
+                    Ok(new_logs) => shared::broadcast(&self.cst, Message::NewLogs(new_logs))?,
+                    // Logs are stored concurrently, so they can be produced faster than forwarded.
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!(skipped, "cluster is lagged behind new logs; they are not replicated");
+                    }
+                    Err(e) => return Err(e.into()),
+                },
                 _ = shutdown_rx.changed() => {
                     debug!("received shutdown signal; stop routine");
                     return Ok(());
@@ -89,7 +113,7 @@ impl Cluster {
     }
 }
 
-async fn listen(
+pub(crate) async fn listen(
     mut stream: TcpStream,
     log_storage: LogStoragePointer,
 ) -> Result<(), Box<dyn std::error::Error>> {
@@ -107,8 +131,17 @@ async fn listen(
         // Delete new line.
         buf.pop();
         match message_type {
-            NEW_LOG_MESSAGE_TYPE => log_storage.lock().await.store(buf).await?,
-            _ => error!("unknown first byte on cluster message: {}", buf[0]),
+            NEW_LOG_MESSAGE_TYPE => {
+                // Replicas store logs with the same keys as the origin node.
+                let key = buf
+                    .get(..KEY_LENGTH)
+                    .and_then(|x| std::str::from_utf8(x).ok())
+                    .and_then(|x| Key::from_str_radix(x, 16).ok())
+                    .ok_or("invalid log key in cluster message")?;
+                let data = buf.split_off(KEY_LENGTH);
+                log_storage.store_with_key(key, data).await?
+            }
+            _ => error!("unknown first byte on cluster message: {}", message_type),
         }
     }
     Ok(())
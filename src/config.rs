@@ -2,34 +2,154 @@ use std::env;
 
 const ENV_SOCKET_ADDR: &str = "SOCKET_ADDR";
 const ENV_INDEX_NAME: &str = "INDEX";
+const ENV_INDEX_PATH: &str = "INDEX_PATH";
+const ENV_INDEX_MAX_DEPTH: &str = "INDEX_MAX_DEPTH";
+const ENV_INDEX_MAX_FIELDS: &str = "INDEX_MAX_FIELDS";
+const ENV_INDEX_CHECKPOINT_INTERVAL: &str = "INDEX_CHECKPOINT_INTERVAL";
+const ENV_TEXT_FIELDS: &str = "TEXT_FIELDS";
+const ENV_TEXT_STEMMING: &str = "TEXT_STEMMING";
+const ENV_TEXT_STOP_WORDS: &str = "TEXT_STOP_WORDS";
 const ENV_STORAGE_NAME: &str = "STORAGE";
 const ENV_CLUSTER_ADDRS: &str = "CLUSTER_ADDRS";
+const ENV_NODE_ID: &str = "NODE_ID";
+const ENV_TIMESTAMP_FIELD: &str = "TIMESTAMP_FIELD";
+const ENV_TIMESTAMP_FORMATS: &str = "TIMESTAMP_FORMATS";
+const ENV_STORAGE_PATH: &str = "STORAGE_PATH";
+const ENV_STORAGE_SEGMENT_SIZE: &str = "STORAGE_SEGMENT_SIZE";
+const ENV_STORAGE_COMPRESSION: &str = "STORAGE_COMPRESSION";
+const ENV_INGEST_FRAMING: &str = "INGEST_FRAMING";
+const ENV_INGEST_FORMAT: &str = "INGEST_FORMAT";
+const ENV_INGEST_MAX_RECORD_SIZE: &str = "INGEST_MAX_RECORD_SIZE";
+const ENV_RETENTION_MAX_AGE: &str = "RETENTION_MAX_AGE";
+const ENV_RETENTION_MAX_BYTES: &str = "RETENTION_MAX_BYTES";
+const ENV_RETENTION_MAX_RECORDS: &str = "RETENTION_MAX_RECORDS";
+const ENV_RETENTION_INTERVAL: &str = "RETENTION_INTERVAL";
 
 const DEFAULT_SOCKET_ADDR: &str = "127.0.0.1:6669";
 const DEFAULT_INDEX_NAME: &str = "nonsense";
+const DEFAULT_INDEX_MAX_DEPTH: usize = 10;
+const DEFAULT_INDEX_MAX_FIELDS: usize = 1000;
+const DEFAULT_INDEX_CHECKPOINT_INTERVAL: u64 = 60; // seconds
+const DEFAULT_TEXT_FIELDS: &str = "message";
 const DEFAULT_STORAGE_NAME: &str = "in_memory";
+const DEFAULT_STORAGE_PATH: &str = "./data";
+const DEFAULT_STORAGE_SEGMENT_SIZE: u64 = 64 * 1024 * 1024; // 64MB
+const DEFAULT_STORAGE_COMPRESSION: &str = "none";
+const DEFAULT_INGEST_FRAMING: &str = "newline";
+const DEFAULT_INGEST_FORMAT: &str = "auto";
+const DEFAULT_INGEST_MAX_RECORD_SIZE: usize = 1024 * 1024; // 1MB
+const DEFAULT_TIMESTAMP_FIELD: &str = "time";
+const DEFAULT_TIMESTAMP_FORMATS: &str = "rfc3339,unix_ns";
+const DEFAULT_RETENTION_INTERVAL: u64 = 60; // seconds
 
 pub(crate) struct Config {
     pub(crate) socket_addr: String,
     pub(crate) index_name: String,
+    pub(crate) index_path: String,
+    pub(crate) index_max_depth: usize,
+    pub(crate) index_max_fields: usize,
+    pub(crate) index_checkpoint_interval: u64, // seconds
+    // Fields and stop words are separated by commas.
+    pub(crate) text_fields: String,
+    pub(crate) text_stemming: bool,
+    pub(crate) text_stop_words: String,
     pub(crate) storage_name: String,
     pub(crate) cluster_addrs: String,
+    pub(crate) node_id: u16,
+    pub(crate) storage_path: String,
+    pub(crate) storage_segment_size: u64,
+    pub(crate) storage_compression: String,
+    pub(crate) ingest_framing: String,
+    pub(crate) ingest_format: String,
+    pub(crate) ingest_max_record_size: usize,
+    pub(crate) timestamp_field: String,
+    pub(crate) timestamp_formats: String,
+    // Zero retention limits are disabled.
+    pub(crate) retention_max_age: u64, // seconds
+    pub(crate) retention_max_bytes: u64,
+    pub(crate) retention_max_records: u64,
+    pub(crate) retention_interval: u64, // seconds
 }
 
 impl Config {
-    pub(super) fn new() -> Self {
+    pub(super) fn new() -> Result<Self, Box<dyn std::error::Error>> {
         let socket_addr =
             env::var(ENV_SOCKET_ADDR).unwrap_or_else(|_| DEFAULT_SOCKET_ADDR.to_string());
         let index_name =
             env::var(ENV_INDEX_NAME).unwrap_or_else(|_| DEFAULT_INDEX_NAME.to_string());
+        let index_path = env::var(ENV_INDEX_PATH).unwrap_or_default();
+        let index_max_depth = parse_env(ENV_INDEX_MAX_DEPTH)?.unwrap_or(DEFAULT_INDEX_MAX_DEPTH);
+        let index_max_fields = parse_env(ENV_INDEX_MAX_FIELDS)?.unwrap_or(DEFAULT_INDEX_MAX_FIELDS);
+        let index_checkpoint_interval =
+            parse_env(ENV_INDEX_CHECKPOINT_INTERVAL)?.unwrap_or(DEFAULT_INDEX_CHECKPOINT_INTERVAL);
+        let text_fields =
+            env::var(ENV_TEXT_FIELDS).unwrap_or_else(|_| DEFAULT_TEXT_FIELDS.to_string());
+        let text_stemming = parse_env(ENV_TEXT_STEMMING)?.unwrap_or_default();
+        let text_stop_words = env::var(ENV_TEXT_STOP_WORDS).unwrap_or_default();
         let storage_name =
             env::var(ENV_STORAGE_NAME).unwrap_or_else(|_| DEFAULT_STORAGE_NAME.to_string());
         let cluster_addrs = env::var(ENV_CLUSTER_ADDRS).unwrap_or_default();
-        Self {
+        let node_id = parse_env(ENV_NODE_ID)?.unwrap_or_default();
+        let storage_path =
+            env::var(ENV_STORAGE_PATH).unwrap_or_else(|_| DEFAULT_STORAGE_PATH.to_string());
+        let storage_segment_size =
+            parse_env(ENV_STORAGE_SEGMENT_SIZE)?.unwrap_or(DEFAULT_STORAGE_SEGMENT_SIZE);
+        let storage_compression = env::var(ENV_STORAGE_COMPRESSION)
+            .unwrap_or_else(|_| DEFAULT_STORAGE_COMPRESSION.to_string());
+        let ingest_framing =
+            env::var(ENV_INGEST_FRAMING).unwrap_or_else(|_| DEFAULT_INGEST_FRAMING.to_string());
+        let ingest_format =
+            env::var(ENV_INGEST_FORMAT).unwrap_or_else(|_| DEFAULT_INGEST_FORMAT.to_string());
+        let ingest_max_record_size =
+            parse_env(ENV_INGEST_MAX_RECORD_SIZE)?.unwrap_or(DEFAULT_INGEST_MAX_RECORD_SIZE);
+        let timestamp_field =
+            env::var(ENV_TIMESTAMP_FIELD).unwrap_or_else(|_| DEFAULT_TIMESTAMP_FIELD.to_string());
+        let timestamp_formats = env::var(ENV_TIMESTAMP_FORMATS)
+            .unwrap_or_else(|_| DEFAULT_TIMESTAMP_FORMATS.to_string());
+        let retention_max_age = parse_env(ENV_RETENTION_MAX_AGE)?.unwrap_or_default();
+        let retention_max_bytes = parse_env(ENV_RETENTION_MAX_BYTES)?.unwrap_or_default();
+        let retention_max_records = parse_env(ENV_RETENTION_MAX_RECORDS)?.unwrap_or_default();
+        let retention_interval =
+            parse_env(ENV_RETENTION_INTERVAL)?.unwrap_or(DEFAULT_RETENTION_INTERVAL);
+        Ok(Self {
             socket_addr,
             index_name,
+            index_path,
+            index_max_depth,
+            index_max_fields,
+            index_checkpoint_interval,
+            text_fields,
+            text_stemming,
+            text_stop_words,
             storage_name,
             cluster_addrs,
+            node_id,
+            storage_path,
+            storage_segment_size,
+            storage_compression,
+            ingest_framing,
+            ingest_format,
+            ingest_max_record_size,
+            timestamp_field,
+            timestamp_formats,
+            retention_max_age,
+            retention_max_bytes,
+            retention_max_records,
+            retention_interval,
+        })
+    }
+}
+
+fn parse_env<T>(name: &str) -> Result<Option<T>, Box<dyn std::error::Error>>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    match env::var(name) {
+        Ok(value) => {
+            let value = value.parse().map_err(|e| format!("invalid {} env value: {}", name, e))?;
+            Ok(Some(value))
         }
+        Err(_) => Ok(None),
     }
 }
@@ -0,0 +1,249 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::value::{flatten_object, Value};
+use super::FlattenLimits;
+
+/// Statistics over matched logs.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Aggregation {
+    // Number of matched logs.
+    Count,
+    // Top values of the field by number of logs.
+    Terms { field: String, size: usize },
+    // Min, max, sum and average of numeric values of the field.
+    Stats { field: String },
+    // Percents are from 0 to 100.
+    Percentiles { field: String, percents: Vec<f64> },
+}
+
+impl Aggregation {
+    pub(crate) fn field(&self) -> Option<&str> {
+        match self {
+            Aggregation::Count => None,
+            Aggregation::Terms { field, .. }
+            | Aggregation::Stats { field }
+            | Aggregation::Percentiles { field, .. } => Some(field),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum AggregationResult {
+    Count(usize),
+    Terms {
+        count: usize,
+        // Values with number of logs ordered by the number.
+        buckets: Vec<(Value, usize)>,
+        // Number of logs in the rest buckets.
+        other: usize,
+    },
+    Stats {
+        count: usize,
+        // Is not set if there are no numeric values.
+        stats: Option<Stats>,
+    },
+    Percentiles {
+        count: usize,
+        // Percent with its value, values are not set if there are no numeric values.
+        percentiles: Vec<(f64, Option<Value>)>,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Stats {
+    // Number of aggregated numeric values.
+    pub(crate) values: usize,
+    pub(crate) min: Value,
+    pub(crate) max: Value,
+    pub(crate) sum: f64,
+    pub(crate) avg: f64,
+}
+
+/// Number of matched logs in time buckets.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Histogram {
+    // Time range in nanoseconds, from is inclusive and to is exclusive.
+    pub(crate) from: u64,
+    pub(crate) to: u64,
+    // Bucket size in nanoseconds, buckets are aligned to unix epoch.
+    pub(crate) interval: u64,
+    // Logs in every bucket are counted by values of this field too.
+    pub(crate) split: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct HistogramBucket {
+    // Start time of the bucket in nanoseconds.
+    pub(crate) start: u64,
+    pub(crate) count: usize,
+    pub(crate) split: ValueCounts,
+}
+
+/// Builds buckets of the whole histogram time range, so buckets without logs are returned too.
+pub(crate) struct HistogramBuilder {
+    from: u64,
+    to: u64,
+    interval: u64,
+    buckets: Vec<HistogramBucket>,
+}
+
+impl HistogramBuilder {
+    pub(crate) fn new(histogram: &Histogram) -> Self {
+        let interval = histogram.interval.max(1);
+        let start = histogram.from - histogram.from % interval;
+        let buckets = (start..histogram.to)
+            .step_by(interval as usize)
+            .map(|start| HistogramBucket {
+                start,
+                ..HistogramBucket::default()
+            })
+            .collect();
+        Self {
+            from: histogram.from,
+            to: histogram.to,
+            interval,
+            buckets,
+        }
+    }
+
+    /// Logs outside of the time range are skipped.
+    pub(crate) fn add(&mut self, timestamp: u64) {
+        if let Some(bucket) = self.bucket(timestamp) {
+            bucket.count += 1;
+        }
+    }
+
+    /// Value should be added once for every log.
+    pub(crate) fn add_value(&mut self, timestamp: u64, value: &Value) {
+        if let Some(bucket) = self.bucket(timestamp) {
+            match bucket.split.get_mut(value) {
+                Some(count) => *count += 1,
+                None => {
+                    bucket.split.insert(value.clone(), 1);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn build(self) -> Vec<HistogramBucket> {
+        self.buckets
+    }
+
+    fn bucket(&mut self, timestamp: u64) -> Option<&mut HistogramBucket> {
+        if timestamp < self.from || timestamp >= self.to {
+            return None;
+        }
+        let start = self.buckets.first()?.start;
+        self.buckets.get_mut(((timestamp - start) / self.interval) as usize)
+    }
+}
+
+/// Number of logs for every value of the aggregated field.
+/// Log is counted once for a value even if it has it several times in an array.
+pub(crate) type ValueCounts = BTreeMap<Value, usize>;
+
+/// Counts field values of the logs data, it is used if the index can't aggregate itself.
+pub(crate) fn count_values<'a>(
+    field: &str,
+    logs: impl Iterator<Item = &'a [u8]>,
+    limits: &FlattenLimits,
+) -> ValueCounts {
+    let mut counts = ValueCounts::new();
+    for data in logs {
+        let Ok(serde_json::Value::Object(obj)) = serde_json::from_slice(data) else {
+            continue;
+        };
+        for value in field_values(&obj, field, limits) {
+            *counts.entry(value).or_default() += 1;
+        }
+    }
+    counts
+}
+
+/// Returns distinct values of the field, so array values are counted once for the log.
+pub(crate) fn field_values(
+    obj: &serde_json::Map<String, serde_json::Value>,
+    field: &str,
+    limits: &FlattenLimits,
+) -> BTreeSet<Value> {
+    flatten_object(obj, limits)
+        .into_iter()
+        .filter(|(name, _)| name == field)
+        .map(|(_, value)| Value::from_json(value))
+        .collect()
+}
+
+/// Computes aggregation from the number of matched logs and counts of their field values.
+pub(crate) fn aggregate(
+    aggregation: &Aggregation,
+    count: usize,
+    counts: ValueCounts,
+) -> AggregationResult {
+    match aggregation {
+        Aggregation::Count => AggregationResult::Count(count),
+        Aggregation::Terms { size, .. } => {
+            let mut buckets: Vec<(Value, usize)> = counts.into_iter().collect();
+            // Values with the same number of logs are ordered by values.
+            buckets.sort_by(|x, y| y.1.cmp(&x.1).then_with(|| x.0.cmp(&y.0)));
+            let other = buckets.iter().skip(*size).map(|x| x.1).sum();
+            buckets.truncate(*size);
+            AggregationResult::Terms {
+                count,
+                buckets,
+                other,
+            }
+        }
+        Aggregation::Stats { .. } => {
+            let numbers = numbers(counts);
+            let stats = match (numbers.first(), numbers.last()) {
+                (Some((min, _)), Some((max, _))) => {
+                    let values: usize = numbers.iter().map(|x| x.1).sum();
+                    let sum: f64 = numbers.iter().map(|(x, count)| to_f64(x) * *count as f64).sum();
+                    Some(Stats {
+                        values,
+                        min: min.clone(),
+                        max: max.clone(),
+                        sum,
+                        avg: sum / values as f64,
+                    })
+                }
+                _ => None,
+            };
+            AggregationResult::Stats { count, stats }
+        }
+        Aggregation::Percentiles { percents, .. } => {
+            let numbers = numbers(counts);
+            let values: usize = numbers.iter().map(|x| x.1).sum();
+            let percentiles = percents
+                .iter()
+                .map(|percent| (*percent, percentile(&numbers, values, *percent)))
+                .collect();
+            AggregationResult::Percentiles { count, percentiles }
+        }
+    }
+}
+
+// Only numeric values are aggregated by stats and percentiles.
+fn numbers(counts: ValueCounts) -> Vec<(Value, usize)> {
+    counts.into_iter().filter(|(value, _)| matches!(value, Value::Number(_))).collect()
+}
+
+// Nearest rank percentile of sorted numbers with their counts.
+fn percentile(numbers: &[(Value, usize)], values: usize, percent: f64) -> Option<Value> {
+    let rank = ((percent / 100.0 * values as f64).ceil() as usize).max(1);
+    let mut seen = 0;
+    for (value, count) in numbers {
+        seen += count;
+        if seen >= rank {
+            return Some(value.clone());
+        }
+    }
+    None
+}
+
+fn to_f64(value: &Value) -> f64 {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        _ => 0.0,
+    }
+}
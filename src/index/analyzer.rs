@@ -0,0 +1,103 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+
+/// Word of the text with its position, positions are used to match phrases.
+pub(crate) type Token = (u32, String);
+
+/// Splits values of text fields into words, so they can be searched by words and phrases
+/// instead of the exact match of the whole value.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Analyzer {
+    // Nested fields are separated by dots.
+    pub(crate) fields: Vec<String>,
+    pub(crate) stemming: bool,
+    pub(crate) stop_words: BTreeSet<String>,
+}
+
+impl Analyzer {
+    pub(crate) fn new(cfg: &Config) -> Self {
+        let list = |value: &str| -> Vec<String> {
+            value.split(',').map(str::trim).filter(|x| !x.is_empty()).map(str::to_string).collect()
+        };
+        Self {
+            fields: list(&cfg.text_fields),
+            stemming: cfg.text_stemming,
+            stop_words: list(&cfg.text_stop_words.to_lowercase()).into_iter().collect(),
+        }
+    }
+
+    pub(crate) fn fields(&self) -> &[String] {
+        &self.fields
+    }
+
+    pub(crate) fn is_text_field(&self, field: &str) -> bool {
+        self.fields.iter().any(|x| x == field)
+    }
+
+    /// Splits text into lower case words.
+    /// Stop words are skipped, but they keep their positions,
+    /// so phrase with a stop word matches only the same number of words between other ones.
+    pub(crate) fn tokenize(&self, text: &str) -> Vec<Token> {
+        text.split(|c: char| !c.is_alphanumeric())
+            .filter(|x| !x.is_empty())
+            .map(str::to_lowercase)
+            .zip(0..)
+            .filter(|(word, _)| !self.stop_words.contains(word))
+            .map(|(word, position)| match self.stemming {
+                true => (position, stem(&word)),
+                false => (position, word),
+            })
+            .collect()
+    }
+}
+
+/// Returns true if phrase tokens follow each other in the text tokens at the same distances.
+/// Text tokens should be sorted by positions.
+pub(crate) fn contains_phrase(text: &[Token], phrase: &[Token]) -> bool {
+    let Some((first_position, first_word)) = phrase.first() else {
+        return false;
+    };
+    text.iter().filter(|(_, word)| word == first_word).any(|(start, _)| {
+        phrase.iter().all(|(position, word)| {
+            let position = start + (position - first_position);
+            text.binary_search_by_key(&position, |x| x.0).is_ok_and(|i| &text[i].1 == word)
+        })
+    })
+}
+
+// Light stemmer which strips common English suffixes.
+// The same stemmer is applied to queries, so stems don't have to be real words.
+fn stem(word: &str) -> String {
+    if word.len() <= 3 || !word.bytes().all(|x| x.is_ascii_lowercase()) {
+        return word.to_string();
+    }
+    if let Some(stem) = word.strip_suffix("ies") {
+        return format!("{}y", stem);
+    }
+    if word.ends_with("ss") || word.ends_with("us") || word.ends_with("is") {
+        return word.to_string();
+    }
+    if let Some(stem) = word.strip_suffix('s') {
+        return stem.to_string();
+    }
+    for suffix in ["ing", "ed"] {
+        match word.strip_suffix(suffix) {
+            Some(stem) if stem.len() >= 3 => return undouble(stem).to_string(),
+            _ => (),
+        }
+    }
+    word.to_string()
+}
+
+// Removes doubled consonant left after the suffix, like "stopped" -> "stopp" -> "stop".
+fn undouble(stem: &str) -> &str {
+    let bytes = stem.as_bytes();
+    let last = bytes[bytes.len() - 1];
+    if last == bytes[bytes.len() - 2] && !b"aeioulsz".contains(&last) {
+        return &stem[..stem.len() - 1];
+    }
+    stem
+}
@@ -6,10 +6,14 @@ pub(crate) enum Error {
     UnknownIndexType(String),
     #[error("failed to decode data: {0}")]
     DecodeData(String),
-    #[error("invalid query syntax")]
-    QuerySyntax,
+    #[error("unsupported query: {0}")]
+    Unsupported(String),
+    #[error("unsupported aggregation: {0}")]
+    AggregationUnsupported(String),
     #[error("data not found")]
     NotFound,
     #[error("internal error: {0}")]
     Internal(String),
+    #[error("checkpoint error: {0}")]
+    Checkpoint(String),
 }
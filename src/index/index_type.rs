@@ -1,12 +1,14 @@
 use std::fmt::{Display, Formatter};
 
 const UNKNOWN: &str = "unknown";
+#[cfg(feature = "index_tantivy")]
 const TANTIVY: &str = "tantivy";
 #[cfg(feature = "index_nonsense")]
 const NONSENSE: &str = "nonsense";
 
 pub(crate) enum IndexType {
     Unknown,
+    #[cfg(feature = "index_tantivy")]
     Tantivy,
     #[cfg(feature = "index_nonsense")]
     Nonsense,
@@ -16,6 +18,7 @@ impl Display for IndexType {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let str = match self {
             IndexType::Unknown => UNKNOWN.to_string(),
+            #[cfg(feature = "index_tantivy")]
             IndexType::Tantivy => TANTIVY.to_string(),
             #[cfg(feature = "index_nonsense")]
             IndexType::Nonsense => NONSENSE.to_string(),
@@ -27,6 +30,7 @@ impl Display for IndexType {
 impl From<&str> for IndexType {
     fn from(str: &str) -> Self {
         match str {
+            #[cfg(feature = "index_tantivy")]
             TANTIVY => IndexType::Tantivy,
             #[cfg(feature = "index_nonsense")]
             NONSENSE => IndexType::Nonsense,
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::ops::{Bound, RangeBounds};
+
+use crate::query::{wildcard_to_regex, Query};
+
+use super::analyzer::{contains_phrase, Analyzer, Token};
+use super::error::Error;
+use super::value::{compile_regex, flatten_object, Value};
+use super::FlattenLimits;
+
+// Flattened log fields with all their values.
+type Fields = HashMap<String, Vec<Value>>;
+
+/// Query compiled to check single logs without an index.
+/// It is used to filter new logs for live subscriptions.
+pub(crate) struct Matcher {
+    root: Node,
+    // Logs are flattened as in the index, so they are matched the same way.
+    limits: FlattenLimits,
+    // Text fields are tokenized as in the index.
+    analyzer: Analyzer,
+}
+
+enum Node {
+    Term {
+        field: String,
+        values: Vec<Value>,
+    },
+    Range {
+        field: String,
+        from: Bound<Value>,
+        to: Bound<Value>,
+    },
+    Prefix {
+        field: String,
+        prefix: String,
+    },
+    Pattern {
+        field: String,
+        regex: regex::Regex,
+    },
+    // Phrase is searched in any of the fields.
+    Phrase {
+        fields: Vec<String>,
+        phrase: Vec<Token>,
+    },
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+}
+
+impl Matcher {
+    pub(crate) fn new(
+        query: &Query,
+        limits: FlattenLimits,
+        analyzer: &Analyzer,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            root: compile(query, analyzer)?,
+            limits,
+            analyzer: analyzer.clone(),
+        })
+    }
+
+    /// Returns false for data which can't be indexed.
+    pub(crate) fn is_match(&self, data: &[u8]) -> bool {
+        let Ok(serde_json::Value::Object(obj)) = serde_json::from_slice(data) else {
+            return false;
+        };
+        let mut fields: Fields = HashMap::new();
+        for (name, value) in flatten_object(&obj, &self.limits) {
+            fields.entry(name).or_default().push(Value::from_json(value));
+        }
+        self.root.is_match(&fields, &self.analyzer)
+    }
+}
+
+impl Node {
+    // Field with multiple values is matched if any of its values is matched.
+    fn is_match(&self, fields: &Fields, analyzer: &Analyzer) -> bool {
+        let any = |field: &str, f: &dyn Fn(&Value) -> bool| {
+            fields.get(field).is_some_and(|values| values.iter().any(f))
+        };
+        match self {
+            Node::Term { field, values } => any(field, &|x| values.contains(x)),
+            Node::Range { field, from, to } => {
+                any(field, &|x| (from.as_ref(), to.as_ref()).contains(x))
+            }
+            Node::Prefix { field, prefix } => {
+                any(field, &|x| matches!(x, Value::String(x) if x.starts_with(prefix)))
+            }
+            Node::Pattern { field, regex } => {
+                any(field, &|x| matches!(x, Value::String(x) if regex.is_match(x)))
+            }
+            Node::Phrase { fields, phrase } => fields.iter().any(|field| {
+                any(field, &|x| match x {
+                    Value::String(text) => contains_phrase(&analyzer.tokenize(text), phrase),
+                    _ => false,
+                })
+            }),
+            Node::And(left, right) => {
+                left.is_match(fields, analyzer) && right.is_match(fields, analyzer)
+            }
+            Node::Or(left, right) => {
+                left.is_match(fields, analyzer) || right.is_match(fields, analyzer)
+            }
+            Node::Not(node) => !node.is_match(fields, analyzer),
+        }
+    }
+}
+
+fn compile(query: &Query, analyzer: &Analyzer) -> Result<Node, Error> {
+    let compile = |query: &Query| compile(query, analyzer).map(Box::new);
+    let node = match query {
+        Query::Term { field, value } => {
+            let term = Node::Term {
+                field: field.clone(),
+                values: Value::candidates(value),
+            };
+            let phrase = analyzer.tokenize(value);
+            // Values of text fields are matched exactly or as phrases.
+            if !analyzer.is_text_field(field) || phrase.is_empty() {
+                return Ok(term);
+            }
+            let phrase = Node::Phrase {
+                fields: vec![field.clone()],
+                phrase,
+            };
+            Node::Or(Box::new(term), Box::new(phrase))
+        }
+        Query::Range { field, from, to } => {
+            let (from, to) = Value::range(from, to);
+            Node::Range {
+                field: field.clone(),
+                from,
+                to,
+            }
+        }
+        Query::Prefix { field, value } => Node::Prefix {
+            field: field.clone(),
+            prefix: value.clone(),
+        },
+        Query::Wildcard { field, value } => Node::Pattern {
+            field: field.clone(),
+            regex: compile_regex(&wildcard_to_regex(value))?,
+        },
+        Query::Regex { field, value } => Node::Pattern {
+            field: field.clone(),
+            regex: compile_regex(value)?,
+        },
+        Query::Text(text) => {
+            if analyzer.fields().is_empty() {
+                return Err(Error::Unsupported("there are no text fields".to_string()));
+            }
+            let phrase = analyzer.tokenize(text);
+            if phrase.is_empty() {
+                return Err(Error::Unsupported(format!("there are no words in \"{}\"", text)));
+            }
+            Node::Phrase {
+                fields: analyzer.fields().to_vec(),
+                phrase,
+            }
+        }
+        Query::And(left, right) => Node::And(compile(left)?, compile(right)?),
+        Query::Or(left, right) => Node::Or(compile(left)?, compile(right)?),
+        Query::Not(query) => Node::Not(compile(query)?),
+    };
+    Ok(node)
+}
@@ -1,34 +1,261 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::str::FromStr;
+
 use tracing::info;
 
+use crate::config::Config;
 #[cfg(feature = "index_nonsense")]
 use crate::index::nonsense::Nonsense;
+#[cfg(feature = "index_tantivy")]
 use crate::index::tantivy::Tantivy;
-use crate::log_storage::{Key, Skip};
+use crate::log_storage::Key;
+use crate::query::Query;
 
+use aggregation::{Aggregation, AggregationResult, Histogram, HistogramBucket};
+use analyzer::Analyzer;
 use error::Error;
 use index_type::IndexType;
 
+pub(crate) mod aggregation;
+pub(crate) mod analyzer;
 pub(crate) mod error;
 mod index_type;
+pub(crate) mod matcher;
 #[cfg(feature = "index_nonsense")]
 mod nonsense;
+#[cfg(feature = "index_tantivy")]
 mod tantivy;
+pub(crate) mod value;
+
+/// Found log with its creation time in nanoseconds.
+/// Hits are ordered by creation time and by key for the same time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Hit {
+    pub(crate) timestamp: u64,
+    pub(crate) key: Key,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Order {
+    Asc,
+    Desc,
+    // Ascending order of keys, which is the order logs were stored in.
+    Keys,
+}
+
+impl Order {
+    fn cmp(&self, left: &Hit, right: &Hit) -> Ordering {
+        match self {
+            Order::Asc => left.cmp(right),
+            Order::Desc => right.cmp(left),
+            Order::Keys => left.key.cmp(&right.key),
+        }
+    }
+}
+
+impl FromStr for Order {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "asc" => Ok(Order::Asc),
+            "desc" => Ok(Order::Desc),
+            _ => Err(format!("unknown order: {}", s)),
+        }
+    }
+}
+
+/// Points to the last returned hit, so the next page starts right after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Cursor(Hit);
+
+impl Cursor {
+    // Only the key is compared for logs ordered by keys, so the time is not needed.
+    pub(crate) fn after_key(key: Key) -> Self {
+        Cursor(Hit { timestamp: 0, key })
+    }
+
+    pub(crate) fn key(&self) -> Key {
+        self.0.key
+    }
+}
+
+impl Display for Cursor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.0.timestamp, self.0.key)
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("invalid cursor: {}", s);
+        let (timestamp, key) = s.split_once('-').ok_or_else(err)?;
+        Ok(Cursor(Hit {
+            timestamp: timestamp.parse().map_err(|_| err())?,
+            key: key.parse().map_err(|_| err())?,
+        }))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct FindOptions {
+    // Only logs created at or after this time are returned.
+    pub(crate) from: Option<u64>,
+    // Only logs created before this time are returned.
+    pub(crate) to: Option<u64>,
+    pub(crate) order: Order,
+    pub(crate) limit: usize,
+    pub(crate) cursor: Option<Cursor>,
+}
+
+impl Default for FindOptions {
+    fn default() -> Self {
+        Self {
+            from: None,
+            to: None,
+            order: Order::Desc,
+            limit: usize::MAX,
+            cursor: None,
+        }
+    }
+}
+
+impl FindOptions {
+    fn contains(&self, timestamp: u64) -> bool {
+        self.from.is_none_or(|from| timestamp >= from) && self.to.is_none_or(|to| timestamp < to)
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct FindResult {
+    // Hits ordered by creation time.
+    pub(crate) hits: Vec<Hit>,
+    // Number of all matched logs in the time range, not only returned ones.
+    pub(crate) total: usize,
+    // Is set if there are more hits after returned ones.
+    pub(crate) cursor: Option<Cursor>,
+}
 
-pub(crate) type FindResult = Vec<Key>;
+/// Limits of fields of a single log, which protect the index from logs with too many fields.
+/// Zero limit is disabled.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct FlattenLimits {
+    // Objects and arrays nested deeper are indexed as strings.
+    pub(crate) max_depth: usize,
+    // Number of indexed values, array elements are counted separately.
+    pub(crate) max_fields: usize,
+}
+
+impl FlattenLimits {
+    pub(crate) fn new(cfg: &Config) -> Self {
+        Self {
+            max_depth: cfg.index_max_depth,
+            max_fields: cfg.index_max_fields,
+        }
+    }
+}
 
 pub(crate) type Index = Box<dyn _Index + Send + Sync>;
 
+/// Copy of the index state which is written to the checkpoint without locking the index.
+pub(crate) type Snapshot = Box<dyn _Snapshot + Send>;
+
+pub(crate) trait _Snapshot {
+    // Persists the snapshot, so it is loaded on restart instead of indexing all logs again.
+    fn write(&self) -> Result<(), Error>;
+}
+
 pub(crate) trait _Index {
-    fn index(&mut self, key: Key, data: &[u8]) -> Result<(), Error>;
-    fn find(&self, query: &str, skip: Skip) -> Result<FindResult, Error>;
+    // Timestamp is a creation time of the log in nanoseconds.
+    fn index(&mut self, key: Key, timestamp: u64, data: &[u8]) -> Result<(), Error>;
+    // Data is the same as indexed one, so index doesn't need to keep it to find the log values.
+    fn delete(&mut self, key: Key, data: &[u8]) -> Result<(), Error>;
+    // Deletes logs which data is not available anymore.
+    fn forget(&mut self, keys: &HashSet<Key>) -> Result<(), Error>;
+    // Returns keys of all indexed logs.
+    fn keys(&self) -> Result<HashSet<Key>, Error>;
+    // Returns the snapshot to persist or nothing if the index is not persisted.
+    fn snapshot(&self) -> Result<Option<Snapshot>, Error>;
+    // Returns one page of matched logs ordered by their creation time.
+    fn find(&self, query: &Query, options: &FindOptions) -> Result<FindResult, Error>;
+    // Aggregates matched logs in the time range of the options without reading their data,
+    // logs are aggregated from the storage if the index doesn't support it.
+    fn aggregate(
+        &self,
+        _query: &Query,
+        _options: &FindOptions,
+        _aggregation: &Aggregation,
+    ) -> Result<AggregationResult, Error> {
+        Err(Error::AggregationUnsupported("index can't aggregate logs".to_string()))
+    }
+    // Counts matched logs in time buckets by their creation time,
+    // logs are split by field values from the storage if the index doesn't support it.
+    fn histogram(
+        &self,
+        _query: &Query,
+        _histogram: &Histogram,
+    ) -> Result<Vec<HistogramBucket>, Error> {
+        Err(Error::AggregationUnsupported("index can't build histograms".to_string()))
+    }
+}
+
+// Selects one page of hits without sorting all of them.
+fn paginate(mut hits: Vec<Hit>, options: &FindOptions) -> FindResult {
+    hits.retain(|hit| options.contains(hit.timestamp));
+    let total = hits.len();
+    if let Some(Cursor(cursor)) = options.cursor {
+        hits.retain(|hit| options.order.cmp(hit, &cursor) == Ordering::Greater);
+    }
+    let has_more = hits.len() > options.limit;
+    if has_more {
+        hits.select_nth_unstable_by(options.limit, |x, y| options.order.cmp(x, y));
+        hits.truncate(options.limit);
+    }
+    hits.sort_unstable_by(|x, y| options.order.cmp(x, y));
+    let cursor = if has_more {
+        hits.last().copied().map(Cursor)
+    } else {
+        None
+    };
+    FindResult {
+        hits,
+        total,
+        cursor,
+    }
+}
+
+/// Checks that data can be indexed, all indexes work only with JSON objects.
+pub(crate) fn validate(data: &[u8]) -> Result<(), Error> {
+    match serde_json::from_slice(data) {
+        Ok(serde_json::Value::Object(_)) => Ok(()),
+        Ok(_) => Err(Error::DecodeData("data should be an object".to_string())),
+        Err(e) => Err(Error::DecodeData(e.to_string())),
+    }
 }
 
-pub(super) fn new_index(index_name: &str) -> Result<Index, Error> {
+// Index is kept only in memory if the path is empty.
+pub(super) fn new_index(
+    index_name: &str,
+    index_path: &str,
+    limits: FlattenLimits,
+    analyzer: Analyzer,
+) -> Result<Index, Error> {
     let index_type = index_name.into();
+    let index_path = Some(Path::new(index_path)).filter(|x| !x.as_os_str().is_empty());
     let index: Index = match index_type {
         #[cfg(feature = "index_nonsense")]
-        IndexType::Nonsense => Box::new(Nonsense::new()),
-        IndexType::Tantivy => Box::new(Tantivy::new()?),
+        IndexType::Nonsense => Box::new(Nonsense::new(index_path, limits, analyzer)?),
+        #[cfg(feature = "index_tantivy")]
+        IndexType::Tantivy if index_path.is_some() => {
+            return Err(Error::Checkpoint("tantivy index can't be persisted".to_string()))
+        }
+        #[cfg(feature = "index_tantivy")]
+        IndexType::Tantivy => Box::new(Tantivy::new(limits, analyzer)?),
         IndexType::Unknown => return Err(Error::UnknownIndexType(index_name.to_string())),
     };
     info!(index_type = &index_type.to_string(), "using as an index");
@@ -37,44 +264,284 @@ pub(super) fn new_index(index_name: &str) -> Result<Index, Error> {
 
 #[cfg(test)]
 mod tests {
-    use crate::{index::*, shared};
+    use crate::{index::*, query, shared};
 
     const LOG1: &str = r#"{"level":"debug","message":"test-1","vars":{"id":1}}"#;
     const LOG2: &str = r#"{"level":"info","message":"test-2","vars":{"id":2}}"#;
     const LOG3: &str = r#"{"level":"error","message":"test-3","vars":{"id":3}}"#;
     const LOG4: &str = r#"{"level":"debug","message":"test-4","vars":{"id":4}}"#;
+    const LOG5: &str = r#"{"level":"warn","status":500,"latency":250.5,"ok":false,"url":"/b"}"#;
+    const LOG6: &str = r#"{"level":"warn","status":404,"latency":250,"ok":true,"url":"/a"}"#;
+    const LOG7: &str =
+        r#"{"level":"trace","a":{"b":{"c":1}},"tags":["prod","eu"],"items":[{"id":1},{"id":[2]}]}"#;
 
-    #[ignore = "tantivy is not implemented"]
+    #[cfg(feature = "index_tantivy")]
     #[test]
-    fn test_tantivy() {}
+    fn test_tantivy() {
+        let mut index = new_index(
+            IndexType::Tantivy.to_string().as_str(),
+            "",
+            FlattenLimits::default(),
+            analyzer(),
+        )
+        .unwrap();
+        fill_index(&mut index);
+        test_index(&index);
+        {
+            let res = index.index(5, shared::now_as_nanos_u64().unwrap(), r#"0"#.as_bytes());
+            assert!(res.is_err());
+            assert_eq!(
+                res.unwrap_err().to_string(),
+                "failed to decode data: tantivy index can't work without objects"
+            );
+        }
+        test_nested_objects(&mut index);
+        test_time_range(&index);
+        test_boolean_queries(&index);
+        test_typed_queries(&mut index);
+        test_pattern_queries(&index);
+        test_delete(&mut index);
+        test_limits(IndexType::Tantivy);
+        {
+            // Free text search over message field.
+            let entries = find(&index, "test").unwrap();
+            assert_eq!(4, entries.len());
+            let entries = find(&index, "3").unwrap();
+            assert_eq!(vec![3], entries);
+        }
+    }
 
+    #[cfg(feature = "index_nonsense")]
     #[test]
     fn test_nonsense() {
-        let mut index = new_index(IndexType::Nonsense.to_string().as_str()).unwrap();
+        let mut index = new_index(
+            IndexType::Nonsense.to_string().as_str(),
+            "",
+            FlattenLimits::default(),
+            analyzer(),
+        )
+        .unwrap();
         fill_index(&mut index);
         test_index(&index);
         {
-            let res = index.index(5, r#"0"#.as_bytes());
+            let res = index.index(5, shared::now_as_nanos_u64().unwrap(), r#"0"#.as_bytes());
             assert!(res.is_err());
             assert_eq!(
                 res.unwrap_err().to_string(),
                 "failed to decode data: nonsense storage can't work without objects"
             );
         }
-        test_nested_objects(&index);
-        test_skip(&index);
+        test_nested_objects(&mut index);
+        test_time_range(&index);
+        test_boolean_queries(&index);
+        test_typed_queries(&mut index);
+        test_pattern_queries(&index);
+        test_delete(&mut index);
+        test_limits(IndexType::Nonsense);
+    }
+
+    #[cfg(feature = "index_nonsense")]
+    #[test]
+    fn test_matcher() {
+        let mut index = new_index(
+            IndexType::Nonsense.to_string().as_str(),
+            "",
+            FlattenLimits::default(),
+            analyzer(),
+        )
+        .unwrap();
+        let logs = [LOG1, LOG2, LOG3, LOG4, LOG5, LOG6, LOG7];
+        for (key, log) in logs.iter().enumerate() {
+            index.index(key as Key, shared::now_as_nanos_u64().unwrap(), log.as_bytes()).unwrap();
+        }
+        // Matcher should find the same logs as index.
+        for query in [
+            "level:debug",
+            "vars.id:1",
+            "(level:debug OR level:info) AND NOT vars.id:1",
+            "status:500.0",
+            "latency:[250 TO 250.5}",
+            "url:[/a TO /b}",
+            "ok:true OR status<=404",
+            "message:test-3*",
+            "message:t?st-?",
+            r"url:/\/(a|b)/ AND status<500",
+            "NOT level:unknown",
+            "tags:eu AND a.b.c:1",
+            "items.id>=2",
+        ] {
+            let mut expected = find(&index, query).unwrap_or_default();
+            expected.sort();
+            let matcher = matcher::Matcher::new(
+                &query::parse(query).unwrap(),
+                FlattenLimits::default(),
+                &analyzer(),
+            )
+            .unwrap();
+            let keys: Vec<Key> = (0..logs.len())
+                .filter(|key| matcher.is_match(logs[*key].as_bytes()))
+                .map(|key| key as Key)
+                .collect();
+            assert_eq!(expected, keys, "query: {}", query);
+        }
+        let matcher = matcher::Matcher::new(
+            &query::parse("TEST 3").unwrap(),
+            FlattenLimits::default(),
+            &analyzer(),
+        )
+        .unwrap();
+        assert!(matcher.is_match(LOG3.as_bytes()));
+        assert!(!matcher.is_match(LOG2.as_bytes()));
+        assert!(!matcher.is_match(b"0"));
+    }
+
+    #[test]
+    fn test_text_search() {
+        #[cfg(feature = "index_nonsense")]
+        check_text_search(IndexType::Nonsense);
+        // Tantivy index tokenizes text fields by the same analyzer.
+        #[cfg(feature = "index_tantivy")]
+        check_text_search(IndexType::Tantivy);
+    }
+
+    fn check_text_search(index_type: IndexType) {
+        let analyzer = Analyzer {
+            fields: vec!["message".to_string(), "error.message".to_string()],
+            stemming: true,
+            stop_words: ["by".to_string(), "was".to_string()].into(),
+        };
+        let mut index = new_index(
+            index_type.to_string().as_str(),
+            "",
+            FlattenLimits::default(),
+            analyzer.clone(),
+        )
+        .unwrap();
+        let logs = [
+            r#"{"message":"Connection reset by peer","level":"reset"}"#,
+            r#"{"message":"connection was reset","error":{"message":"Stopped connections"}}"#,
+            r#"{"message":["reset","connection"]}"#,
+            r#"{"message":"timeout"}"#,
+            r#"{"message":"timeout"}"#,
+        ];
+        for (key, log) in logs.iter().enumerate() {
+            index.index(key as Key, shared::now_as_nanos_u64().unwrap(), log.as_bytes()).unwrap();
+        }
+        let cases: [(&str, Vec<Key>); 12] = [
+            (r#"message:"connection reset""#, vec![0]),
+            (r#"message:"Connection reset by peer""#, vec![0]),
+            // Stop words are skipped, but they are counted as words between other ones.
+            (r#"message:"connection was reset""#, vec![1]),
+            (r#"message:"connection by reset""#, vec![1]),
+            (r#""reset by peer""#, vec![0]),
+            // Phrase is not matched across array elements.
+            (r#""reset connection""#, vec![]),
+            ("reset connection", vec![0, 1, 2]),
+            ("stopping", vec![1]),
+            ("error.message:connection", vec![1]),
+            ("level:reset AND NOT message:peer", vec![]),
+            (r#"message:"by""#, vec![]),
+            // Logs with only some words of the phrase are skipped.
+            (r#"message:"connection timeout""#, vec![]),
+        ];
+        for (query, expected) in cases {
+            let mut entries = find(&index, query).unwrap_or_default();
+            entries.sort();
+            assert_eq!(expected, entries, "{}: {}", index_type, query);
+            // Matcher should find the same logs as index.
+            let matcher = matcher::Matcher::new(
+                &query::parse(query).unwrap(),
+                FlattenLimits::default(),
+                &analyzer,
+            )
+            .unwrap();
+            let keys: Vec<Key> = (0..logs.len())
+                .filter(|key| matcher.is_match(logs[*key].as_bytes()))
+                .map(|key| key as Key)
+                .collect();
+            assert_eq!(expected, keys, "query: {}", query);
+        }
+        assert!(find(&index, "by").is_err());
+        index.delete(1, logs[1].as_bytes()).unwrap();
+        assert!(find(&index, "stopping").is_err());
+        assert_eq!(vec![0], find(&index, r#"message:"connection reset""#).unwrap());
+    }
+
+    #[cfg(feature = "index_nonsense")]
+    #[test]
+    fn test_nonsense_checkpoint() {
+        let path = std::env::temp_dir().join(format!("loghell-test-{}", fastrand::u64(..)));
+        let path = path.to_str().unwrap();
+        let mut index = new_index(
+            IndexType::Nonsense.to_string().as_str(),
+            path,
+            FlattenLimits::default(),
+            analyzer(),
+        )
+        .unwrap();
+        fill_index(&mut index);
+        index.index(5, shared::now_as_nanos_u64().unwrap(), LOG5.as_bytes()).unwrap();
+        index.snapshot().unwrap().unwrap().write().unwrap();
+        index.index(6, shared::now_as_nanos_u64().unwrap(), LOG6.as_bytes()).unwrap();
+
+        // Logs indexed after the checkpoint are not loaded.
+        let mut index = new_index(
+            IndexType::Nonsense.to_string().as_str(),
+            path,
+            FlattenLimits::default(),
+            analyzer(),
+        )
+        .unwrap();
+        assert_eq!(HashSet::from([1, 2, 3, 4, 5]), index.keys().unwrap());
+        test_index(&index);
+        test_nested_objects(&mut index);
+        test_time_range(&index);
+        assert_eq!(vec![5], find(&index, "latency:250.5 AND status>=500").unwrap());
+        assert_eq!(4, find(&index, "test").unwrap().len());
+        index.forget(&HashSet::from([1, 5])).unwrap();
+        assert_eq!(vec![4], find(&index, "level:debug").unwrap());
+        assert!(find(&index, "level:warn").is_err());
+        assert_eq!(3, find(&index, "test").unwrap().len());
+        index.snapshot().unwrap().unwrap().write().unwrap();
+
+        // Checkpoint is skipped if text fields are analyzed differently.
+        let index = new_index(
+            IndexType::Nonsense.to_string().as_str(),
+            path,
+            FlattenLimits::default(),
+            Analyzer::default(),
+        )
+        .unwrap();
+        assert!(index.keys().unwrap().is_empty());
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    fn analyzer() -> Analyzer {
+        Analyzer {
+            fields: vec!["message".to_string()],
+            ..Analyzer::default()
+        }
+    }
+
+    fn find(index: &Index, query: &str) -> Result<Vec<Key>, Error> {
+        find_with(index, query, &FindOptions::default())
+    }
+
+    fn find_with(index: &Index, query: &str, options: &FindOptions) -> Result<Vec<Key>, Error> {
+        let found = index.find(&query::parse(query).unwrap(), options)?;
+        Ok(found.hits.into_iter().map(|x| x.key).collect())
     }
 
     fn fill_index(index: &mut Index) {
-        index.index(1, LOG1.as_bytes()).unwrap();
-        index.index(2, LOG2.as_bytes()).unwrap();
-        index.index(3, LOG3.as_bytes()).unwrap();
-        index.index(4, LOG4.as_bytes()).unwrap();
+        index.index(1, shared::now_as_nanos_u64().unwrap(), LOG1.as_bytes()).unwrap();
+        index.index(2, shared::now_as_nanos_u64().unwrap(), LOG2.as_bytes()).unwrap();
+        index.index(3, shared::now_as_nanos_u64().unwrap(), LOG3.as_bytes()).unwrap();
+        index.index(4, shared::now_as_nanos_u64().unwrap(), LOG4.as_bytes()).unwrap();
     }
 
     fn test_index(index: &Index) {
         {
-            let find_res = index.find("level:debug", 0);
+            let find_res = find(index, "level:debug");
             assert!(find_res.is_ok());
             let entries = find_res.unwrap();
             assert_eq!(2, entries.len());
@@ -86,37 +553,180 @@ mod tests {
             }
         }
         {
-            let find_res = index.find("level:info", 0);
+            let find_res = find(index, "level:info");
             assert!(find_res.is_ok());
             let entries = find_res.unwrap();
             assert_eq!(1, entries.len());
             assert_eq!(2, entries[0]);
         }
         {
-            let find_res = index.find("level:error", 0);
+            let find_res = find(index, "level:error");
             assert!(find_res.is_ok());
             let entries = find_res.unwrap();
             assert_eq!(1, entries.len());
             assert_eq!(3, entries[0]);
         }
         {
-            let find_res = index.find("level:unknown", 0);
+            let find_res = find(index, "level:unknown");
             assert!(find_res.is_err());
         }
     }
 
-    fn test_nested_objects(index: &Index) {
-        let find_res = index.find("vars.id:1", 0);
+    // Fields after the limits are not indexed.
+    fn test_limits(index_type: IndexType) {
+        let limits = FlattenLimits {
+            max_depth: 3,
+            max_fields: 3,
+        };
+        let mut index = new_index(index_type.to_string().as_str(), "", limits, analyzer()).unwrap();
+        index.index(7, shared::now_as_nanos_u64().unwrap(), LOG7.as_bytes()).unwrap();
+        assert_eq!(vec![7], find(&index, "a.b.c:1").unwrap());
+        assert_eq!(vec![7], find(&index, "items.id:1").unwrap());
+        // Array elements are nested values too, so this one is too deep.
+        assert!(find(&index, "items.id:2").unwrap_or_default().is_empty());
+        assert!(find(&index, "level:trace").unwrap_or_default().is_empty());
+    }
+
+    fn test_nested_objects(index: &mut Index) {
+        let find_res = find(index, "vars.id:1");
         assert!(find_res.is_ok());
         let entries = find_res.unwrap();
         assert_eq!(1, entries.len());
         assert_eq!(1, entries[0]);
+
+        index.index(7, shared::now_as_nanos_u64().unwrap(), LOG7.as_bytes()).unwrap();
+        // Every array element is indexed under the same field.
+        for query in [
+            "a.b.c:1",
+            "tags:prod",
+            "tags:eu AND tags:prod",
+            "items.id:1",
+            "items.id:2",
+        ] {
+            assert_eq!(vec![7], find(index, query).unwrap(), "query: {}", query);
+        }
+        index.delete(7, LOG7.as_bytes()).unwrap();
+        assert!(find(index, "tags:prod").unwrap_or_default().is_empty());
+    }
+
+    fn test_time_range(index: &Index) {
+        let options = |order: Order, limit: usize, cursor: Option<Cursor>| FindOptions {
+            order,
+            limit,
+            cursor,
+            ..FindOptions::default()
+        };
+        let found =
+            index.find(&query::parse("level:debug").unwrap(), &options(Order::Asc, 1, None));
+        let found = found.unwrap();
+        assert_eq!(vec![1], found.hits.iter().map(|x| x.key).collect::<Vec<Key>>());
+        assert_eq!(2, found.total);
+        let cursor = found.cursor;
+        assert!(cursor.is_some());
+        assert_eq!(
+            vec![4],
+            find_with(index, "level:debug", &options(Order::Asc, 1, cursor)).unwrap()
+        );
+        assert_eq!(
+            vec![4, 1],
+            find_with(index, "level:debug", &options(Order::Desc, 2, None)).unwrap()
+        );
+        assert_eq!(
+            vec![4],
+            find_with(index, "level:debug", &options(Order::Keys, 1, Some(Cursor::after_key(1))))
+                .unwrap()
+        );
+
+        let entries = find_with(
+            index,
+            "level:debug",
+            &FindOptions {
+                from: Some(shared::now_as_nanos_u64().unwrap()),
+                ..FindOptions::default()
+            },
+        );
+        assert!(entries.unwrap_or_default().is_empty());
+        let entries = find_with(
+            index,
+            "level:debug",
+            &FindOptions {
+                to: Some(shared::now_as_nanos_u64().unwrap()),
+                ..FindOptions::default()
+            },
+        );
+        assert_eq!(2, entries.unwrap().len());
+    }
+
+    fn test_boolean_queries(index: &Index) {
+        let cases: [(&str, Vec<Key>); 7] = [
+            ("level:debug AND vars.id:4", vec![4]),
+            ("level:debug vars.id:4", vec![4]),
+            ("level:info OR level:error", vec![2, 3]),
+            ("level:info OR level:unknown", vec![2]),
+            ("NOT level:debug", vec![2, 3]),
+            ("(level:debug OR level:info) AND NOT vars.id:1", vec![2, 4]),
+            ("level:debug AND NOT (vars.id:1 OR vars.id:4)", vec![]),
+        ];
+        for (query, expected) in cases {
+            let mut entries = find(index, query).unwrap_or_default();
+            entries.sort();
+            assert_eq!(expected, entries, "query: {}", query);
+        }
+    }
+
+    fn test_typed_queries(index: &mut Index) {
+        index.index(5, shared::now_as_nanos_u64().unwrap(), LOG5.as_bytes()).unwrap();
+        index.index(6, shared::now_as_nanos_u64().unwrap(), LOG6.as_bytes()).unwrap();
+        let cases: [(&str, Vec<Key>); 14] = [
+            ("status:500", vec![5]),
+            ("status:500.0", vec![5]),
+            ("ok:true", vec![6]),
+            ("status>=500", vec![5]),
+            ("status>404", vec![5]),
+            ("status<=404", vec![6]),
+            ("latency > 250", vec![5]),
+            ("latency>=250", vec![5, 6]),
+            ("latency:[250 TO 250.5}", vec![6]),
+            ("latency:{250 TO *]", vec![5]),
+            ("vars.id:[2 TO 3]", vec![2, 3]),
+            ("vars.id:{4 TO 4}", vec![]),
+            ("url:[/a TO /b}", vec![6]),
+            ("level:warn AND url>/a", vec![5]),
+        ];
+        for (query, expected) in cases {
+            let mut entries = find(index, query).unwrap_or_default();
+            entries.sort();
+            assert_eq!(expected, entries, "query: {}", query);
+        }
     }
 
-    fn test_skip(index: &Index) {
-        let entries = index.find("level:debug", 0).unwrap();
-        assert_eq!(2, entries.len());
-        let entries = index.find("level:debug", shared::now_as_nanos_u64().unwrap()).unwrap();
-        assert_eq!(0, entries.len());
+    fn test_delete(index: &mut Index) {
+        index.delete(4, LOG4.as_bytes()).unwrap();
+        assert_eq!(vec![1], find(index, "level:debug").unwrap());
+        assert!(find(index, "vars.id:4").is_err());
+        let mut entries = find(index, "NOT level:debug").unwrap();
+        entries.sort();
+        assert_eq!(vec![2, 3, 5, 6], entries);
+        index.index(4, shared::now_as_nanos_u64().unwrap(), LOG4.as_bytes()).unwrap();
+        assert_eq!(2, find(index, "level:debug").unwrap().len());
+    }
+
+    fn test_pattern_queries(index: &Index) {
+        let cases: [(&str, Vec<Key>); 9] = [
+            ("message:test*", vec![1, 2, 3, 4]),
+            ("message:test-3*", vec![3]),
+            ("message:*3", vec![3]),
+            ("message:t?st-?", vec![1, 2, 3, 4]),
+            ("message:te*-[12]", vec![]),
+            ("level:d*g", vec![1, 4]),
+            (r"message:/test-[12]/", vec![1, 2]),
+            (r"message:/test/", vec![]),
+            (r"url:/\/(a|b)/ AND status<500", vec![6]),
+        ];
+        for (query, expected) in cases {
+            let mut entries = find(index, query).unwrap_or_default();
+            entries.sort();
+            assert_eq!(expected, entries, "query: {}", query);
+        }
     }
 }
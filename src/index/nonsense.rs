@@ -1,33 +1,328 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::{BufReader, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
 
-use crate::index::{FindResult, _Index};
-use crate::log_storage::{Key, Skip};
-use crate::shared;
+use serde::{Deserialize, Serialize};
+use tracing::info;
 
+use crate::index::{
+    _Index, _Snapshot, paginate, FindOptions, FindResult, FlattenLimits, Hit, Snapshot,
+};
+use crate::log_storage::Key;
+use crate::query::{wildcard_prefix, wildcard_to_regex, Query};
+
+use super::aggregation::{
+    aggregate, Aggregation, AggregationResult, Histogram, HistogramBucket, HistogramBuilder,
+    ValueCounts,
+};
+use super::analyzer::{contains_phrase, Analyzer, Token};
 use super::error::Error;
+use super::value::{compile_regex, flatten_object, is_empty_range, Value};
 
-#[derive(Hash, PartialEq, Eq, Clone, Copy)]
-struct Data {
-    key: Key,
-    created_at: u64,
+type Values = HashMap<String, BTreeMap<Value, HashSet<Key>>>; // field_name : { field_value : keys }
+type Documents = HashMap<Key, u64>; // key : created_at
+type Words = HashMap<String, HashMap<String, HashMap<Key, Vec<u32>>>>; // field_name : { word : { key : positions } }
+
+const CHECKPOINT_FILE: &str = "nonsense.json";
+const CHECKPOINT_TEMP_FILE: &str = "nonsense.json.tmp";
+const CHECKPOINT_VERSION: u32 = 2;
+
+// Values are saved as lists as JSON object keys can be only strings.
+type SavedValues<'a> = Vec<(&'a String, Vec<(&'a Value, &'a HashSet<Key>)>)>;
+type LoadedValues = Vec<(String, Vec<(Value, HashSet<Key>)>)>;
+
+#[derive(Serialize)]
+struct CheckpointRef<'a> {
+    version: u32,
+    documents: &'a Documents,
+    values: SavedValues<'a>,
+    words: &'a Words,
+    analyzer: &'a Analyzer,
 }
 
-type Values = HashMap<String, HashMap<String, HashSet<Data>>>; // field_name : { field_value : (key, created_at) }
+#[derive(Deserialize)]
+struct Checkpoint {
+    version: u32,
+    documents: Documents,
+    values: LoadedValues,
+    // Previous versions don't have text fields.
+    #[serde(default)]
+    words: Words,
+    #[serde(default)]
+    analyzer: Analyzer,
+}
+
+// Index state is cloned, so it is serialized after the index is unlocked.
+struct CheckpointSnapshot {
+    path: PathBuf,
+    documents: Documents,
+    values: Values,
+    words: Words,
+    analyzer: Analyzer,
+}
+
+impl _Snapshot for CheckpointSnapshot {
+    // Checkpoint is written to the temporary file first,
+    // so the previous checkpoint is kept if we fail in the middle.
+    fn write(&self) -> Result<(), Error> {
+        let checkpoint = CheckpointRef {
+            version: CHECKPOINT_VERSION,
+            documents: &self.documents,
+            values: self
+                .values
+                .iter()
+                .map(|(name, values)| (name, values.iter().collect()))
+                .collect(),
+            words: &self.words,
+            analyzer: &self.analyzer,
+        };
+        let temp_path = self.path.join(CHECKPOINT_TEMP_FILE);
+        let mut writer = BufWriter::new(fs::File::create(&temp_path).map_err(map_err)?);
+        serde_json::to_writer(&mut writer, &checkpoint).map_err(map_err)?;
+        writer.flush().map_err(map_err)?;
+        writer.get_ref().sync_all().map_err(map_err)?;
+        fs::rename(&temp_path, self.path.join(CHECKPOINT_FILE)).map_err(map_err)
+    }
+}
 
 pub(super) struct Nonsense {
     values: Values,
+    documents: Documents,
+    // Positional index of words of text fields to search phrases.
+    words: Words,
+    // Directory for checkpoints, index is not persisted without it.
+    path: Option<PathBuf>,
+    limits: FlattenLimits,
+    analyzer: Analyzer,
 }
 
 impl Nonsense {
-    pub(super) fn new() -> Self {
-        Nonsense {
+    pub(super) fn new(
+        path: Option<&Path>,
+        limits: FlattenLimits,
+        analyzer: Analyzer,
+    ) -> Result<Self, Error> {
+        let mut nonsense = Nonsense {
             values: HashMap::new(),
+            documents: HashMap::new(),
+            words: HashMap::new(),
+            path: path.map(|x| x.to_path_buf()),
+            limits,
+            analyzer,
+        };
+        if let Some(path) = path {
+            fs::create_dir_all(path).map_err(map_err)?;
+            nonsense.load(&path.join(CHECKPOINT_FILE))?;
+        }
+        Ok(nonsense)
+    }
+
+    fn load(&mut self, path: &Path) -> Result<(), Error> {
+        let file = match fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(map_err(e)),
+        };
+        let checkpoint: Checkpoint =
+            serde_json::from_reader(BufReader::new(file)).map_err(map_err)?;
+        if checkpoint.version > CHECKPOINT_VERSION {
+            return Err(Error::Checkpoint(format!(
+                "unsupported checkpoint version: {}",
+                checkpoint.version
+            )));
+        }
+        // Index is rebuilt from the storage if text fields are indexed differently.
+        if checkpoint.version < CHECKPOINT_VERSION || checkpoint.analyzer != self.analyzer {
+            info!("nonsense index checkpoint is outdated, index is rebuilt");
+            return Ok(());
+        }
+        self.documents = checkpoint.documents;
+        self.words = checkpoint.words;
+        self.values = checkpoint
+            .values
+            .into_iter()
+            .map(|(name, values)| (name, values.into_iter().collect()))
+            .collect();
+        info!(documents = self.documents.len(), "nonsense index checkpoint is loaded");
+        Ok(())
+    }
+
+    // Evaluates query as set operations over keys of matched documents.
+    fn evaluate(&self, query: &Query) -> Result<HashSet<Key>, Error> {
+        match query {
+            Query::Term { field, value } => {
+                let mut keys: HashSet<Key> = HashSet::new();
+                if let Some(values) = self.values.get(field) {
+                    for value in Value::candidates(value) {
+                        keys.extend(values.get(&value).into_iter().flatten());
+                    }
+                }
+                // Values of text fields are matched exactly or as phrases.
+                if self.analyzer.is_text_field(field) {
+                    keys.extend(self.match_phrase(field, &self.analyzer.tokenize(value)));
+                }
+                Ok(keys)
+            }
+            Query::Range { field, from, to } => {
+                let mut keys: HashSet<Key> = HashSet::new();
+                let (from, to) = Value::range(from, to);
+                if is_empty_range(&from, &to) {
+                    return Ok(keys);
+                }
+                if let Some(values) = self.values.get(field) {
+                    keys.extend(values.range((from, to)).flat_map(|(_, keys)| keys));
+                }
+                Ok(keys)
+            }
+            Query::Prefix { field, value } => Ok(self.match_strings(field, value, |_| true)),
+            Query::Wildcard { field, value } => {
+                let regex = compile_regex(&wildcard_to_regex(value))?;
+                Ok(self.match_strings(field, wildcard_prefix(value), |x| regex.is_match(x)))
+            }
+            Query::Regex { field, value } => {
+                let regex = compile_regex(value)?;
+                Ok(self.match_strings(field, "", |x| regex.is_match(x)))
+            }
+            Query::Text(text) => {
+                if self.analyzer.fields().is_empty() {
+                    return Err(Error::Unsupported("there are no text fields".to_string()));
+                }
+                let phrase = self.analyzer.tokenize(text);
+                if phrase.is_empty() {
+                    return Err(Error::Unsupported(format!("there are no words in \"{}\"", text)));
+                }
+                let mut keys: HashSet<Key> = HashSet::new();
+                for field in self.analyzer.fields() {
+                    keys.extend(self.match_phrase(field, &phrase));
+                }
+                Ok(keys)
+            }
+            Query::And(left, right) => {
+                let left = self.evaluate(left)?;
+                if left.is_empty() {
+                    return Ok(left);
+                }
+                let right = self.evaluate(right)?;
+                Ok(left.intersection(&right).copied().collect())
+            }
+            Query::Or(left, right) => {
+                let mut left = self.evaluate(left)?;
+                left.extend(self.evaluate(right)?);
+                Ok(left)
+            }
+            Query::Not(query) => {
+                let excluded = self.evaluate(query)?;
+                Ok(self.documents.keys().filter(|key| !excluded.contains(key)).copied().collect())
+            }
+        }
+    }
+
+    // Checks positions only of logs which contain all words of the phrase.
+    fn match_phrase(&self, field: &str, phrase: &[Token]) -> HashSet<Key> {
+        let mut postings: Vec<(&String, &HashMap<Key, Vec<u32>>)> = Vec::new();
+        for (_, word) in phrase {
+            match self.words.get(field).and_then(|words| words.get(word)) {
+                Some(keys) => postings.push((word, keys)),
+                None => return HashSet::new(),
+            }
+        }
+        let Some((_, candidates)) = postings.iter().min_by_key(|(_, keys)| keys.len()) else {
+            return HashSet::new();
+        };
+        let mut keys: HashSet<Key> = HashSet::new();
+        for key in candidates.keys() {
+            // Candidate can miss other words of the phrase.
+            let Some(positions) = postings
+                .iter()
+                .map(|(word, keys)| keys.get(key).map(|positions| (word, positions)))
+                .collect::<Option<Vec<_>>>()
+            else {
+                continue;
+            };
+            let mut text: Vec<Token> = positions
+                .into_iter()
+                .flat_map(|(word, positions)| positions.iter().map(|x| (*x, word.to_string())))
+                .collect();
+            text.sort_unstable();
+            text.dedup();
+            if contains_phrase(&text, phrase) {
+                keys.insert(*key);
+            }
+        }
+        keys
+    }
+
+    fn index_words(&mut self, key: Key, fields: &[(String, &serde_json::Value)]) {
+        // Values of the same field are separated by a position gap,
+        // so phrases are not matched across array elements.
+        let mut offsets: HashMap<&str, u32> = HashMap::new();
+        for (name, value) in fields {
+            let serde_json::Value::String(text) = value else {
+                continue;
+            };
+            if !self.analyzer.is_text_field(name) {
+                continue;
+            }
+            let offset = offsets.entry(name).or_default();
+            let tokens = self.analyzer.tokenize(text);
+            let words = self.words.entry(name.clone()).or_default();
+            for (position, word) in &tokens {
+                words
+                    .entry(word.clone())
+                    .or_default()
+                    .entry(key)
+                    .or_default()
+                    .push(*offset + position);
+            }
+            *offset += tokens.last().map_or(0, |x| x.0) + 2;
         }
     }
+
+    fn delete_words(&mut self, key: Key, fields: &[(String, &serde_json::Value)]) {
+        for (name, value) in fields {
+            let serde_json::Value::String(text) = value else {
+                continue;
+            };
+            let Some(words) = self.words.get_mut(name) else {
+                continue;
+            };
+            for (_, word) in self.analyzer.tokenize(text) {
+                if let Some(keys) = words.get_mut(&word) {
+                    keys.remove(&key);
+                    if keys.is_empty() {
+                        words.remove(&word);
+                    }
+                }
+            }
+            if words.is_empty() {
+                self.words.remove(name);
+            }
+        }
+    }
+
+    // Values in the term dictionary are sorted, so we scan only string values with the prefix.
+    fn match_strings(&self, field: &str, prefix: &str, f: impl Fn(&str) -> bool) -> HashSet<Key> {
+        let mut keys: HashSet<Key> = HashSet::new();
+        let Some(values) = self.values.get(field) else {
+            return keys;
+        };
+        for (value, value_keys) in values.range(Value::String(prefix.to_string())..) {
+            match value {
+                Value::String(value) if value.starts_with(prefix) => {
+                    if f(value) {
+                        keys.extend(value_keys);
+                    }
+                }
+                _ => break,
+            }
+        }
+        keys
+    }
 }
 
 impl _Index for Nonsense {
-    fn index(&mut self, key: Key, data: &[u8]) -> Result<(), Error> {
+    fn index(&mut self, key: Key, timestamp: u64, data: &[u8]) -> Result<(), Error> {
         let data_as_value: serde_json::Value =
             serde_json::from_slice(data).map_err(|e| Error::DecodeData(e.to_string()))?;
         if !data_as_value.is_object() {
@@ -36,36 +331,144 @@ impl _Index for Nonsense {
             ));
         }
         let obj = cast_value_as_object(&data_as_value)?;
-        for (name, value) in obj.iter() {
-            if value.is_object() {
-                for (nested_name, nested_value) in cast_value_as_object(value)? {
-                    let name = format!("{name}.{nested_name}");
-                    do_index(&mut self.values, name, nested_value, key)?;
-                }
+        self.documents.insert(key, timestamp);
+        let fields = flatten_object(obj, &self.limits);
+        self.index_words(key, &fields);
+        for (name, value) in fields {
+            do_index(&mut self.values, name, value, key)?;
+        }
+        Ok(())
+    }
+
+    fn delete(&mut self, key: Key, data: &[u8]) -> Result<(), Error> {
+        if self.documents.remove(&key).is_none() {
+            return Ok(());
+        }
+        let data_as_value: serde_json::Value =
+            serde_json::from_slice(data).map_err(|e| Error::DecodeData(e.to_string()))?;
+        let obj = cast_value_as_object(&data_as_value)?;
+        let fields = flatten_object(obj, &self.limits);
+        self.delete_words(key, &fields);
+        for (name, value) in fields {
+            let Some(ids_by_values) = self.values.get_mut(&name) else {
                 continue;
+            };
+            let value = Value::from_json(value);
+            if let Some(ids) = ids_by_values.get_mut(&value) {
+                ids.remove(&key);
+                if ids.is_empty() {
+                    ids_by_values.remove(&value);
+                }
+            }
+            if ids_by_values.is_empty() {
+                self.values.remove(&name);
             }
-            do_index(&mut self.values, name.clone(), value, key)?;
         }
         Ok(())
     }
 
-    fn find(&self, query: &str, skip: Skip) -> Result<FindResult, Error> {
-        let split: Vec<&str> = query.split(':').collect();
-        if split.len() != 2 {
-            return Err(Error::QuerySyntax);
+    fn forget(&mut self, keys: &HashSet<Key>) -> Result<(), Error> {
+        if keys.is_empty() {
+            return Ok(());
         }
-        let key: &str = split[0];
-        let value: &str = split[1];
-        let entries =
-            self.values.get(key).ok_or(Error::NotFound)?.get(value).ok_or(Error::NotFound)?;
-        let mut res: FindResult = FindResult::new();
-        for entry in entries {
-            if entry.created_at < skip {
-                continue;
+        // We don't know values of forgotten logs, so we have to check all of them.
+        self.documents.retain(|key, _| !keys.contains(key));
+        for ids_by_values in self.values.values_mut() {
+            for ids in ids_by_values.values_mut() {
+                ids.retain(|key| !keys.contains(key));
             }
-            res.push(entry.key)
+            ids_by_values.retain(|_, ids| !ids.is_empty());
         }
-        Ok(res)
+        self.values.retain(|_, ids_by_values| !ids_by_values.is_empty());
+        for words in self.words.values_mut() {
+            for ids in words.values_mut() {
+                ids.retain(|key, _| !keys.contains(key));
+            }
+            words.retain(|_, ids| !ids.is_empty());
+        }
+        self.words.retain(|_, words| !words.is_empty());
+        Ok(())
+    }
+
+    fn keys(&self) -> Result<HashSet<Key>, Error> {
+        Ok(self.documents.keys().copied().collect())
+    }
+
+    fn snapshot(&self) -> Result<Option<Snapshot>, Error> {
+        let Some(path) = &self.path else {
+            return Ok(None);
+        };
+        Ok(Some(Box::new(CheckpointSnapshot {
+            path: path.clone(),
+            documents: self.documents.clone(),
+            values: self.values.clone(),
+            words: self.words.clone(),
+            analyzer: self.analyzer.clone(),
+        })))
+    }
+
+    fn find(&self, query: &Query, options: &FindOptions) -> Result<FindResult, Error> {
+        let keys = self.evaluate(query)?;
+        if keys.is_empty() {
+            return Err(Error::NotFound);
+        }
+        let hits = keys
+            .into_iter()
+            .map(|key| Hit {
+                timestamp: self.documents.get(&key).copied().unwrap_or_default(),
+                key,
+            })
+            .collect();
+        Ok(paginate(hits, options))
+    }
+
+    // Values are counted by their postings, so logs are not decoded.
+    fn aggregate(
+        &self,
+        query: &Query,
+        options: &FindOptions,
+        aggregation: &Aggregation,
+    ) -> Result<AggregationResult, Error> {
+        let mut keys = self.evaluate(query)?;
+        keys.retain(|key| options.contains(self.documents.get(key).copied().unwrap_or_default()));
+        let mut counts = ValueCounts::new();
+        if let Some(values) = aggregation.field().and_then(|field| self.values.get(field)) {
+            for (value, value_keys) in values {
+                let count = match value_keys.len() < keys.len() {
+                    true => value_keys.iter().filter(|key| keys.contains(key)).count(),
+                    false => keys.iter().filter(|key| value_keys.contains(key)).count(),
+                };
+                if count > 0 {
+                    counts.insert(value.clone(), count);
+                }
+            }
+        }
+        Ok(aggregate(aggregation, keys.len(), counts))
+    }
+
+    fn histogram(
+        &self,
+        query: &Query,
+        histogram: &Histogram,
+    ) -> Result<Vec<HistogramBucket>, Error> {
+        let mut builder = HistogramBuilder::new(histogram);
+        let mut keys: HashMap<Key, u64> = HashMap::new();
+        for key in self.evaluate(query)? {
+            let timestamp = self.documents.get(&key).copied().unwrap_or_default();
+            if timestamp >= histogram.from && timestamp < histogram.to {
+                builder.add(timestamp);
+                keys.insert(key, timestamp);
+            }
+        }
+        let split = histogram.split.as_ref().and_then(|field| self.values.get(field));
+        for (value, value_keys) in split.into_iter().flatten() {
+            for key in value_keys {
+                if let Some(timestamp) = keys.get(key) {
+                    builder.add_value(*timestamp, value);
+                }
+            }
+        }
+        Ok(builder.build())
     }
 }
 
@@ -75,13 +478,9 @@ fn do_index(
     value: &serde_json::Value,
     key: Key,
 ) -> Result<(), Error> {
-    let ids_by_values = values.entry(field_name).or_insert_with(HashMap::new);
-    let value = value.to_string().replace('\"', "");
-    let ids = ids_by_values.entry(value).or_insert_with(HashSet::new);
-    ids.insert(Data {
-        key,
-        created_at: shared::now_as_nanos_u64().map_err(|e| Error::Internal(e.to_string()))?,
-    });
+    let ids_by_values = values.entry(field_name).or_default();
+    let ids = ids_by_values.entry(Value::from_json(value)).or_default();
+    ids.insert(key);
     Ok(())
 }
 
@@ -90,3 +489,7 @@ fn cast_value_as_object(
 ) -> Result<&serde_json::Map<String, serde_json::Value>, Error> {
     val.as_object().ok_or(Error::DecodeData("failed to get data as object".to_string()))
 }
+
+fn map_err<T: ToString>(err: T) -> Error {
+    Error::Checkpoint(err.to_string())
+}
@@ -1,24 +1,469 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Bound;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use tantivy::collector::DocSetCollector;
+use tantivy::query::{
+    AllQuery, BooleanQuery, ExistsQuery, Occur, PhraseQuery, Query as TantivyQuery, RangeQuery,
+    RegexQuery, TermQuery,
+};
+use tantivy::schema::{
+    Field, IndexRecordOption, JsonObjectOptions, OwnedValue, Schema, TextFieldIndexing,
+    TextOptions, FAST, INDEXED,
+};
+use tantivy::tokenizer::{PreTokenizedString, Token as TantivyToken};
+use tantivy::{IndexReader, IndexWriter, ReloadPolicy, TantivyDocument, Term};
+
 use crate::{
-    index::{FindResult, _Index},
-    log_storage::{Key, Skip},
+    index::{FindOptions, FindResult, FlattenLimits, Hit, Snapshot, _Index, paginate},
+    log_storage::Key,
+    query::{wildcard_to_regex, Query},
 };
 
+use super::analyzer::{Analyzer, Token};
 use super::error::Error;
+use super::value::{flatten_object, Number, Value};
+
+const FIELD_KEY: &str = "key";
+const FIELD_CREATED_AT: &str = "created_at";
+const FIELD_FIELDS: &str = "fields";
+const FIELD_TEXT: &str = "text";
+
+// Minimal memory budget allowed by tantivy for index writer.
+const WRITER_MEMORY_BUDGET: usize = 15_000_000;
 
-pub(super) struct Tantivy {}
+pub(super) struct Tantivy {
+    reader: IndexReader,
+    writer: Mutex<IndexWriter>,
+    // We commit lazily on the next search to not create segment for every indexed log.
+    dirty: AtomicBool,
+    key: Field,
+    created_at: Field,
+    fields: Field,
+    // Words of text fields are tokenized by the analyzer as in the nonsense index.
+    text: Field,
+    limits: FlattenLimits,
+    analyzer: Analyzer,
+}
 
 impl Tantivy {
-    pub(super) fn new() -> Result<Self, Error> {
-        todo!()
+    pub(super) fn new(limits: FlattenLimits, analyzer: Analyzer) -> Result<Self, Error> {
+        let mut schema_builder = Schema::builder();
+        let key = schema_builder.add_u64_field(FIELD_KEY, INDEXED | FAST);
+        let created_at = schema_builder.add_u64_field(FIELD_CREATED_AT, FAST);
+        // Field values are indexed without tokenization to match them exactly as Nonsense does.
+        // Fast fields are required for range queries.
+        let fields_options = JsonObjectOptions::default()
+            .set_indexing_options(
+                TextFieldIndexing::default()
+                    .set_tokenizer("raw")
+                    .set_index_option(IndexRecordOption::Basic),
+            )
+            .set_fast(Some("raw"));
+        let fields = schema_builder.add_json_field(FIELD_FIELDS, fields_options);
+        // Positions are required for phrase queries.
+        let text_options = TextOptions::default().set_indexing_options(
+            TextFieldIndexing::default().set_index_option(IndexRecordOption::WithFreqsAndPositions),
+        );
+        let text = schema_builder.add_text_field(FIELD_TEXT, text_options);
+        let schema = schema_builder.build();
+
+        let index = tantivy::Index::create_in_ram(schema);
+        let writer = index.writer_with_num_threads(1, WRITER_MEMORY_BUDGET).map_err(map_err)?;
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()
+            .map_err(map_err)?;
+        Ok(Self {
+            reader,
+            writer: Mutex::new(writer),
+            dirty: AtomicBool::new(false),
+            key,
+            created_at,
+            fields,
+            text,
+            limits,
+            analyzer,
+        })
+    }
+
+    fn commit(&self) -> Result<(), Error> {
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+        self.writer.lock().map_err(map_err)?.commit().map_err(map_err)?;
+        self.reader.reload().map_err(map_err)
+    }
+
+    fn build_query(&self, query: &Query) -> Result<Box<dyn TantivyQuery>, Error> {
+        let query: Box<dyn TantivyQuery> = match query {
+            // Values of text fields are matched exactly or as phrases.
+            Query::Term { field, value } => match self.analyzer.tokenize(value) {
+                phrase if self.analyzer.is_text_field(field) && !phrase.is_empty() => {
+                    Box::new(BooleanQuery::new(vec![
+                        (Occur::Should, self.build_field_query(field, value)),
+                        (Occur::Should, self.build_phrase_query(field, &phrase)),
+                    ]))
+                }
+                _ => self.build_field_query(field, value),
+            },
+            Query::Range { field, from, to } => self.build_range_query(field, from, to),
+            Query::Prefix { field, value } => {
+                self.build_regex_query(field, &format!("{}.*", regex::escape(value)))?
+            }
+            Query::Wildcard { field, value } => {
+                self.build_regex_query(field, &wildcard_to_regex(value))?
+            }
+            Query::Regex { field, value } => self.build_regex_query(field, value)?,
+            Query::Text(text) => self.build_text_query(text)?,
+            Query::And(left, right) => Box::new(BooleanQuery::new(vec![
+                (Occur::Must, self.build_query(left)?),
+                (Occur::Must, self.build_query(right)?),
+            ])),
+            Query::Or(left, right) => Box::new(BooleanQuery::new(vec![
+                (Occur::Should, self.build_query(left)?),
+                (Occur::Should, self.build_query(right)?),
+            ])),
+            // Boolean query can't consist only of excluded clauses, so we match all at first.
+            Query::Not(query) => Box::new(BooleanQuery::new(vec![
+                (Occur::Must, Box::new(AllQuery)),
+                (Occur::MustNot, self.build_query(query)?),
+            ])),
+        };
+        Ok(query)
+    }
+
+    // As we don't know the type of the value in the query,
+    // we are looking for every type the value can be parsed to.
+    fn build_field_query(&self, field: &str, value: &str) -> Box<dyn TantivyQuery> {
+        let path = Term::from_field_json_path(self.fields, field, false);
+        let mut terms: Vec<Term> = Vec::new();
+        let mut push = |f: &dyn Fn(&mut Term)| {
+            let mut term = path.clone();
+            f(&mut term);
+            terms.push(term);
+        };
+        for value in Value::candidates(value) {
+            match value {
+                Value::Null => (),
+                Value::Bool(value) => push(&|term| term.append_type_and_fast_value(value)),
+                Value::String(value) => push(&|term| term.append_type_and_str(&value)),
+                Value::Number(number) => {
+                    // Tantivy indexes integers as i64 if they fit and as u64 otherwise.
+                    let integer = match number {
+                        Number::Integer(value) => Some(value),
+                        Number::Float(value) if value.fract() == 0.0 => Some(value as i128),
+                        Number::Float(_) => None,
+                    };
+                    if let Some(value) = integer.and_then(|x| i64::try_from(x).ok()) {
+                        push(&|term| term.append_type_and_fast_value(value));
+                    } else if let Some(value) = integer.and_then(|x| u64::try_from(x).ok()) {
+                        push(&|term| term.append_type_and_fast_value(value));
+                    }
+                    let value = match number {
+                        Number::Integer(value) => value as f64,
+                        Number::Float(value) => value,
+                    };
+                    push(&|term| term.append_type_and_fast_value(value));
+                }
+            }
+        }
+        Box::new(BooleanQuery::new_multiterms_query(terms))
+    }
+
+    fn build_range_query(
+        &self,
+        field: &str,
+        from: &Bound<String>,
+        to: &Bound<String>,
+    ) -> Box<dyn TantivyQuery> {
+        let (from, to) = Value::range(from, to);
+        let numeric = [&from, &to].iter().any(|x| {
+            matches!(x, Bound::Included(Value::Number(_)) | Bound::Excluded(Value::Number(_)))
+        });
+        let from = self.range_bound(field, from, numeric);
+        let to = self.range_bound(field, to, numeric);
+        if let (Bound::Unbounded, Bound::Unbounded) = (&from, &to) {
+            return Box::new(ExistsQuery::new(format!("{}.{}", FIELD_FIELDS, field), false));
+        }
+        Box::new(RangeQuery::new(from, to))
+    }
+
+    // Tantivy keeps values of different types in different columns,
+    // so we don't need boundaries between types which are used by ordered values.
+    fn range_bound(&self, field: &str, bound: Bound<Value>, numeric: bool) -> Bound<Term> {
+        let term = |value: Value| {
+            let mut term = Term::from_field_json_path(self.fields, field, false);
+            match value {
+                Value::Number(Number::Integer(value)) if numeric => {
+                    term.append_type_and_fast_value(value as f64)
+                }
+                Value::Number(Number::Float(value)) if numeric => {
+                    term.append_type_and_fast_value(value)
+                }
+                Value::String(value) if !numeric => term.append_type_and_str(&value),
+                _ => return None,
+            }
+            Some(term)
+        };
+        match bound {
+            Bound::Included(value) => term(value).map_or(Bound::Unbounded, Bound::Included),
+            Bound::Excluded(value) => term(value).map_or(Bound::Unbounded, Bound::Excluded),
+            Bound::Unbounded => Bound::Unbounded,
+        }
+    }
+
+    fn build_regex_query(&self, field: &str, regex: &str) -> Result<Box<dyn TantivyQuery>, Error> {
+        // Terms of the json field consist of path segments separated by \x01,
+        // \x00 as the end of the path, type code and value, so regex has to match all of them.
+        let path: Vec<String> = field.split('.').map(regex::escape).collect();
+        let pattern = format!(r"{}\x00s(?:{})", path.join(r"\x01"), regex);
+        Ok(Box::new(RegexQuery::from_pattern(&pattern, self.fields).map_err(map_err)?))
+    }
+
+    fn build_time_range_query(&self, options: &FindOptions) -> Option<Box<dyn TantivyQuery>> {
+        if options.from.is_none() && options.to.is_none() {
+            return None;
+        }
+        let term = |x: u64| Term::from_field_u64(self.created_at, x);
+        let from = options.from.map_or(Bound::Unbounded, |x| Bound::Included(term(x)));
+        let to = options.to.map_or(Bound::Unbounded, |x| Bound::Excluded(term(x)));
+        Some(Box::new(RangeQuery::new(from, to)))
+    }
+
+    fn build_text_query(&self, text: &str) -> Result<Box<dyn TantivyQuery>, Error> {
+        if self.analyzer.fields().is_empty() {
+            return Err(Error::Unsupported("there are no text fields".to_string()));
+        }
+        let phrase = self.analyzer.tokenize(text);
+        if phrase.is_empty() {
+            return Err(Error::Unsupported(format!("there are no words in \"{}\"", text)));
+        }
+        let queries = self
+            .analyzer
+            .fields()
+            .iter()
+            .map(|field| (Occur::Should, self.build_phrase_query(field, &phrase)))
+            .collect();
+        Ok(Box::new(BooleanQuery::new(queries)))
+    }
+
+    // Phrase positions are relative to the first word, stop words keep their places.
+    fn build_phrase_query(&self, field: &str, phrase: &[Token]) -> Box<dyn TantivyQuery> {
+        let first = phrase.first().map_or(0, |x| x.0);
+        let mut terms: Vec<(usize, Term)> = phrase
+            .iter()
+            .map(|(position, word)| {
+                let term = Term::from_field_text(self.text, &text_term(field, word));
+                ((position - first) as usize, term)
+            })
+            .collect();
+        match terms.len() {
+            1 => Box::new(TermQuery::new(terms.remove(0).1, IndexRecordOption::Basic)),
+            _ => Box::new(PhraseQuery::new_with_offset(terms)),
+        }
+    }
+
+    // Words are prefixed by their field, so phrases are matched in every field separately.
+    fn text_tokens(&self, fields: &[(String, &serde_json::Value)]) -> Vec<TantivyToken> {
+        // Values of the same field are separated by a position gap,
+        // so phrases are not matched across array elements.
+        let mut offsets: HashMap<&str, u32> = HashMap::new();
+        let mut tokens: Vec<TantivyToken> = Vec::new();
+        for (name, value) in fields {
+            let serde_json::Value::String(text) = value else {
+                continue;
+            };
+            if !self.analyzer.is_text_field(name) {
+                continue;
+            }
+            let offset = offsets.entry(name).or_default();
+            let words = self.analyzer.tokenize(text);
+            for (position, word) in &words {
+                tokens.push(TantivyToken {
+                    position: (*offset + position) as usize,
+                    text: text_term(name, word),
+                    ..TantivyToken::default()
+                });
+            }
+            *offset += words.last().map_or(0, |x| x.0) + 2;
+        }
+        tokens
     }
 }
 
 impl _Index for Tantivy {
-    fn index(&mut self, _key: Key, _data: &[u8]) -> Result<(), Error> {
-        todo!()
+    fn index(&mut self, key: Key, timestamp: u64, data: &[u8]) -> Result<(), Error> {
+        let data_as_value: serde_json::Value =
+            serde_json::from_slice(data).map_err(|e| Error::DecodeData(e.to_string()))?;
+        let obj = data_as_value
+            .as_object()
+            .ok_or(Error::DecodeData("tantivy index can't work without objects".to_string()))?;
+
+        let mut doc = TantivyDocument::new();
+        doc.add_u64(self.key, key);
+        doc.add_u64(self.created_at, timestamp);
+        let fields = flatten_object(obj, &self.limits);
+        let tokens = self.text_tokens(&fields);
+        if !tokens.is_empty() {
+            let text = PreTokenizedString {
+                text: String::new(),
+                tokens,
+            };
+            doc.add_pre_tokenized_text(self.text, text);
+        }
+        let fields = nest_fields(fields);
+        doc.add_object(
+            self.fields,
+            fields.iter().map(|(name, value)| (name.clone(), to_owned_value(value))).collect(),
+        );
+        self.writer.get_mut().map_err(map_err)?.add_document(doc).map_err(map_err)?;
+        self.dirty.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    fn delete(&mut self, key: Key, _: &[u8]) -> Result<(), Error> {
+        let writer = self.writer.get_mut().map_err(map_err)?;
+        writer.delete_term(Term::from_field_u64(self.key, key));
+        self.dirty.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    fn forget(&mut self, keys: &HashSet<Key>) -> Result<(), Error> {
+        let writer = self.writer.get_mut().map_err(map_err)?;
+        for key in keys {
+            writer.delete_term(Term::from_field_u64(self.key, *key));
+        }
+        self.dirty.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    fn keys(&self) -> Result<HashSet<Key>, Error> {
+        self.commit()?;
+        let searcher = self.reader.searcher();
+        let docs = searcher.search(&AllQuery, &DocSetCollector).map_err(map_err)?;
+        let mut keys: HashSet<Key> = HashSet::with_capacity(docs.len());
+        for doc in docs {
+            let fast_fields = searcher.segment_reader(doc.segment_ord).fast_fields();
+            let key = fast_fields
+                .u64(FIELD_KEY)
+                .map_err(map_err)?
+                .first(doc.doc_id)
+                .ok_or(Error::Internal("key is missing".to_string()))?;
+            keys.insert(key);
+        }
+        Ok(keys)
+    }
+
+    // Index is kept in memory, so it is not persisted.
+    fn snapshot(&self) -> Result<Option<Snapshot>, Error> {
+        Ok(None)
+    }
+
+    fn find(&self, query: &Query, options: &FindOptions) -> Result<FindResult, Error> {
+        let query = self.build_query(query)?;
+        self.commit()?;
+
+        // Time range is applied by the created at fast field,
+        // so documents out of the range are not collected.
+        let query: Box<dyn TantivyQuery> = match self.build_time_range_query(options) {
+            Some(range) => {
+                Box::new(BooleanQuery::new(vec![(Occur::Must, query), (Occur::Must, range)]))
+            }
+            None => query,
+        };
+        let searcher = self.reader.searcher();
+        let docs = searcher.search(&query, &DocSetCollector).map_err(map_err)?;
+        if docs.is_empty() {
+            return Err(Error::NotFound);
+        }
+        let mut hits: Vec<Hit> = Vec::with_capacity(docs.len());
+        for doc in docs {
+            let fast_fields = searcher.segment_reader(doc.segment_ord).fast_fields();
+            let created_at = fast_fields
+                .u64(FIELD_CREATED_AT)
+                .map_err(map_err)?
+                .first(doc.doc_id)
+                .ok_or(Error::Internal("created at is missing".to_string()))?;
+            let key = fast_fields
+                .u64(FIELD_KEY)
+                .map_err(map_err)?
+                .first(doc.doc_id)
+                .ok_or(Error::Internal("key is missing".to_string()))?;
+            hits.push(Hit {
+                timestamp: created_at,
+                key,
+            })
+        }
+        Ok(paginate(hits, options))
+    }
+}
+
+// We don't use tantivy conversion from serde_json value,
+// because it converts strings which look like dates to the date type,
+// so they can't be found by exact string value.
+// Fields are flattened with limits as in the nonsense index and nested back by dotted names,
+// so values deeper than the limit are strings and fields after the limit are skipped.
+fn nest_fields(
+    fields: Vec<(String, &serde_json::Value)>,
+) -> serde_json::Map<String, serde_json::Value> {
+    let mut obj = serde_json::Map::new();
+    'fields: for (name, value) in fields {
+        let mut path: Vec<&str> = name.split('.').collect();
+        let last = path.pop().expect("split returns at least one part");
+        let mut parent = &mut obj;
+        for part in path {
+            let nested = parent
+                .entry(part)
+                .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()));
+            // Dotted field name can clash with a value of the nested field.
+            let serde_json::Value::Object(nested) = nested else {
+                continue 'fields;
+            };
+            parent = nested;
+        }
+        // Array elements are flattened to separate values of the same field.
+        let value = Value::from_json(value).to_json();
+        match parent.get_mut(last) {
+            Some(serde_json::Value::Array(values)) => values.push(value),
+            Some(old) => *old = serde_json::Value::Array(vec![old.take(), value]),
+            None => {
+                parent.insert(last.to_string(), value);
+            }
+        }
     }
+    obj
+}
+
+fn text_term(field: &str, word: &str) -> String {
+    format!("{}\x00{}", field, word)
+}
 
-    fn find(&self, _query: &str, _skip: Skip) -> Result<FindResult, Error> {
-        todo!()
+fn to_owned_value(value: &serde_json::Value) -> OwnedValue {
+    match value {
+        serde_json::Value::Null => OwnedValue::Null,
+        serde_json::Value::Bool(value) => OwnedValue::Bool(*value),
+        serde_json::Value::Number(number) => {
+            if let Some(value) = number.as_i64() {
+                OwnedValue::I64(value)
+            } else if let Some(value) = number.as_u64() {
+                OwnedValue::U64(value)
+            } else {
+                OwnedValue::F64(number.as_f64().unwrap_or_default())
+            }
+        }
+        serde_json::Value::String(value) => OwnedValue::Str(value.clone()),
+        serde_json::Value::Array(values) => {
+            OwnedValue::Array(values.iter().map(to_owned_value).collect())
+        }
+        serde_json::Value::Object(obj) => OwnedValue::Object(
+            obj.iter().map(|(name, value)| (name.clone(), to_owned_value(value))).collect(),
+        ),
     }
 }
+
+fn map_err<T: ToString>(err: T) -> Error {
+    Error::Internal(err.to_string())
+}
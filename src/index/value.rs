@@ -0,0 +1,235 @@
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::ops::Bound;
+
+use serde::{Deserialize, Serialize};
+
+use super::error::Error;
+use super::FlattenLimits;
+
+/// Typed field value which is stored in the index.
+///
+/// Values of different types are ordered as null < bool < number < string,
+/// so range of values of the same type is a continuous range in ordered collection.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    Number(Number),
+    String(String),
+}
+
+impl Value {
+    pub(crate) fn from_json(value: &serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(value) => Value::Bool(*value),
+            serde_json::Value::Number(number) => match number.as_i64() {
+                Some(value) => Value::Number(Number::Integer(value as i128)),
+                None => match number.as_u64() {
+                    Some(value) => Value::Number(Number::Integer(value as i128)),
+                    None => Value::Number(Number::Float(number.as_f64().unwrap_or_default())),
+                },
+            },
+            serde_json::Value::String(value) => Value::String(value.clone()),
+            // Complex values are stored as strings.
+            value => Value::String(value.to_string().replace('\"', "")),
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        match self {
+            Value::Null => serde_json::Value::Null,
+            Value::Bool(value) => serde_json::Value::Bool(*value),
+            Value::Number(Number::Integer(value)) => match i64::try_from(*value) {
+                Ok(value) => value.into(),
+                Err(_) => (*value as u64).into(),
+            },
+            Value::Number(Number::Float(value)) => (*value).into(),
+            Value::String(value) => serde_json::Value::String(value.clone()),
+        }
+    }
+
+    /// Returns all typed values the raw value from the query can be matched with.
+    pub(crate) fn candidates(raw: &str) -> Vec<Value> {
+        let mut candidates = vec![Value::String(raw.to_string())];
+        if let Some(number) = Number::parse(raw) {
+            candidates.push(Value::Number(number));
+        }
+        match raw {
+            "true" => candidates.push(Value::Bool(true)),
+            "false" => candidates.push(Value::Bool(false)),
+            "null" => candidates.push(Value::Null),
+            _ => (),
+        }
+        candidates
+    }
+
+    /// Converts bounds from the query to the bounds for ordered collection of values.
+    /// If all bounded values are numbers the range contains only numbers,
+    /// otherwise it is a range of strings.
+    pub(crate) fn range(from: &Bound<String>, to: &Bound<String>) -> (Bound<Value>, Bound<Value>) {
+        if let (Bound::Unbounded, Bound::Unbounded) = (from, to) {
+            return (Bound::Unbounded, Bound::Unbounded);
+        }
+        let number = |x: &str| Number::parse(x).map(Value::Number);
+        if let (Some(from), Some(to)) = (map_bound(from, number), map_bound(to, number)) {
+            return (
+                or_bound(from, Bound::Excluded(Value::Bool(true))),
+                or_bound(to, Bound::Excluded(Value::String(String::new()))),
+            );
+        }
+        let string = |x: &str| Some(Value::String(x.to_string()));
+        let from = map_bound(from, string).unwrap_or(Bound::Unbounded);
+        let to = map_bound(to, string).unwrap_or(Bound::Unbounded);
+        (or_bound(from, Bound::Included(Value::String(String::new()))), to)
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Number(Number::Integer(value)) => write!(f, "{}", value),
+            Value::Number(Number::Float(value)) => write!(f, "{}", value),
+            Value::String(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Integers and floats are compared by their numeric values,
+/// so 1 and 1.0 are the same number.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub(crate) enum Number {
+    // i128 is used to fit both i64 and u64.
+    Integer(i128),
+    Float(f64),
+}
+
+impl Number {
+    fn parse(raw: &str) -> Option<Self> {
+        if let Ok(value) = raw.parse::<i128>() {
+            return Some(Number::Integer(value));
+        }
+        // Rust parses "inf" and "NaN" as floats, but we don't want to treat such words as numbers.
+        raw.parse::<f64>().ok().filter(|x| x.is_finite()).map(Number::Float)
+    }
+
+    pub(crate) fn as_f64(&self) -> f64 {
+        match self {
+            Number::Integer(value) => *value as f64,
+            Number::Float(value) => *value,
+        }
+    }
+}
+
+impl Ord for Number {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Number::Integer(a), Number::Integer(b)) => a.cmp(b),
+            (Number::Float(a), Number::Float(b)) => a.total_cmp(b),
+            (Number::Integer(a), Number::Float(b)) => compare_integer_with_float(*a, *b),
+            (Number::Float(a), Number::Integer(b)) => compare_integer_with_float(*b, *a).reverse(),
+        }
+    }
+}
+
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Number {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Number {}
+
+fn compare_integer_with_float(a: i128, b: f64) -> Ordering {
+    // Whole floats are compared as integers to not lose precision on big integers.
+    if b.fract() == 0.0 && b.abs() < i128::MAX as f64 {
+        return a.cmp(&(b as i128));
+    }
+    (a as f64).total_cmp(&b)
+}
+
+// Returns None if bounded value can't be converted.
+fn map_bound(bound: &Bound<String>, f: impl Fn(&str) -> Option<Value>) -> Option<Bound<Value>> {
+    Some(match bound {
+        Bound::Included(value) => Bound::Included(f(value)?),
+        Bound::Excluded(value) => Bound::Excluded(f(value)?),
+        Bound::Unbounded => Bound::Unbounded,
+    })
+}
+
+// Replaces unbounded bound with the default one.
+fn or_bound(bound: Bound<Value>, default: Bound<Value>) -> Bound<Value> {
+    match bound {
+        Bound::Unbounded => default,
+        bound => bound,
+    }
+}
+
+/// Flattens nested objects to fields with dotted names,
+/// every array element is a separate value of the same field.
+pub(crate) fn flatten_object<'a>(
+    obj: &'a serde_json::Map<String, serde_json::Value>,
+    limits: &FlattenLimits,
+) -> Vec<(String, &'a serde_json::Value)> {
+    let mut fields: Vec<(String, &serde_json::Value)> = Vec::with_capacity(obj.len());
+    for (name, value) in obj {
+        flatten(name.clone(), value, 1, limits, &mut fields);
+    }
+    fields
+}
+
+fn flatten<'a>(
+    name: String,
+    value: &'a serde_json::Value,
+    depth: usize,
+    limits: &FlattenLimits,
+    fields: &mut Vec<(String, &'a serde_json::Value)>,
+) {
+    if limits.max_fields > 0 && fields.len() >= limits.max_fields {
+        return;
+    }
+    // Values nested deeper than the limit are indexed as complex values.
+    if limits.max_depth > 0 && depth >= limits.max_depth {
+        fields.push((name, value));
+        return;
+    }
+    match value {
+        serde_json::Value::Object(nested) => {
+            for (nested_name, nested_value) in nested {
+                flatten(format!("{name}.{nested_name}"), nested_value, depth + 1, limits, fields);
+            }
+        }
+        serde_json::Value::Array(values) => {
+            for value in values {
+                flatten(name.clone(), value, depth + 1, limits, fields);
+            }
+        }
+        value => fields.push((name, value)),
+    }
+}
+
+/// Compiles regex which should match the whole value.
+pub(crate) fn compile_regex(regex: &str) -> Result<regex::Regex, Error> {
+    regex::Regex::new(&format!("^(?:{})$", regex)).map_err(|e| Error::Internal(e.to_string()))
+}
+
+/// BTreeMap::range panics on such ranges, so we need to check them before.
+#[cfg(feature = "index_nonsense")]
+pub(crate) fn is_empty_range(from: &Bound<Value>, to: &Bound<Value>) -> bool {
+    match (from, to) {
+        (Bound::Included(from), Bound::Included(to)) => from > to,
+        (Bound::Included(from), Bound::Excluded(to))
+        | (Bound::Excluded(from), Bound::Included(to))
+        | (Bound::Excluded(from), Bound::Excluded(to)) => from >= to,
+        _ => false,
+    }
+}
@@ -0,0 +1,120 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::log_storage::Key;
+
+// Keys are built as Snowflake IDs:
+// 41 bits of milliseconds since epoch, 10 bits of node id and 12 bits of sequence.
+const NODE_ID_BITS: u32 = 10;
+const SEQUENCE_BITS: u32 = 12;
+const MAX_SEQUENCE: u64 = (1 << SEQUENCE_BITS) - 1;
+pub(crate) const MAX_NODE_ID: u16 = (1 << NODE_ID_BITS) - 1;
+// 2023-01-01T00:00:00Z in milliseconds.
+const EPOCH: u64 = 1_672_531_200_000;
+
+/// Generates unique keys which are ordered by creation time.
+/// Keys from the same node are strictly increasing even if system clock goes backwards.
+pub(crate) struct KeyGenerator {
+    node_id: u64,
+    // Milliseconds since epoch of the last generated key.
+    last_timestamp: u64,
+    sequence: u64,
+}
+
+impl KeyGenerator {
+    pub(crate) fn new(node_id: u16) -> Result<Self, String> {
+        if node_id > MAX_NODE_ID {
+            return Err(format!("node id should be from 0 to {}", MAX_NODE_ID));
+        }
+        Ok(Self {
+            node_id: node_id as u64,
+            last_timestamp: 0,
+            sequence: 0,
+        })
+    }
+
+    pub(crate) fn next(&mut self) -> Key {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        self.next_at((now as u64).saturating_sub(EPOCH))
+    }
+
+    /// Makes sure that next keys are greater than the given one,
+    /// so keys stay ordered after restart with clock moved backwards.
+    pub(crate) fn advance(&mut self, key: Key) {
+        let timestamp = key >> (NODE_ID_BITS + SEQUENCE_BITS);
+        let sequence = key & MAX_SEQUENCE;
+        if (timestamp, sequence) > (self.last_timestamp, self.sequence) {
+            self.last_timestamp = timestamp;
+            self.sequence = sequence;
+        }
+    }
+
+    fn next_at(&mut self, timestamp: u64) -> Key {
+        if timestamp > self.last_timestamp {
+            self.last_timestamp = timestamp;
+            self.sequence = 0;
+        } else if self.sequence < MAX_SEQUENCE {
+            self.sequence += 1;
+        } else {
+            // Sequence is exhausted, so we borrow the next millisecond instead of waiting for it.
+            self.last_timestamp += 1;
+            self.sequence = 0;
+        }
+        self.last_timestamp << (NODE_ID_BITS + SEQUENCE_BITS)
+            | self.node_id << SEQUENCE_BITS
+            | self.sequence
+    }
+}
+
+/// Returns the smallest key which can be generated at the given unix time in nanoseconds.
+pub(crate) fn min_key(time: u64) -> Key {
+    (time / 1_000_000).saturating_sub(EPOCH) << (NODE_ID_BITS + SEQUENCE_BITS)
+}
+
+/// Returns unix time in nanoseconds when the key was generated.
+pub(crate) fn key_time(key: Key) -> u64 {
+    ((key >> (NODE_ID_BITS + SEQUENCE_BITS)) + EPOCH).saturating_mul(1_000_000)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::key::*;
+
+    #[test]
+    fn test_key_generator() {
+        let mut generator = KeyGenerator::new(5).unwrap();
+        let first = generator.next_at(100);
+        assert_eq!(100_000_000 + EPOCH * 1_000_000, key_time(first));
+        assert_eq!(5, (first >> SEQUENCE_BITS) & MAX_NODE_ID as u64);
+
+        // Keys are increasing within the same millisecond and when clock goes backwards.
+        let mut last = first;
+        for timestamp in [100, 100, 99, 50, 101] {
+            let key = generator.next_at(timestamp);
+            assert!(key > last);
+            last = key;
+        }
+
+        // Sequence overflow moves key to the next millisecond.
+        let mut generator = KeyGenerator::new(0).unwrap();
+        let mut last = generator.next_at(10);
+        for _ in 0..MAX_SEQUENCE + 1 {
+            let key = generator.next_at(10);
+            assert!(key > last);
+            last = key;
+        }
+        assert_eq!(11_000_000 + EPOCH * 1_000_000, key_time(last));
+
+        // Keys from different nodes don't collide.
+        let mut other = KeyGenerator::new(1).unwrap();
+        assert_ne!(KeyGenerator::new(0).unwrap().next_at(1), other.next_at(1));
+
+        // Restored keys are taken into account.
+        let mut generator = KeyGenerator::new(0).unwrap();
+        generator.advance(last);
+        assert!(generator.next_at(1) > last);
+
+        assert!(KeyGenerator::new(MAX_NODE_ID + 1).is_err());
+        assert!(min_key(key_time(last)) <= last);
+        assert!(min_key(key_time(last) + 1_000_000) > last);
+    }
+}
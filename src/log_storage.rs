@@ -1,36 +1,103 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
-use tokio::sync::Mutex;
+use tracing::{info, warn};
 
-use crate::{index, shared, storage};
+use crate::{
+    config::Config,
+    index,
+    index::{
+        aggregation::{
+            self, Aggregation, AggregationResult, Histogram, HistogramBucket, HistogramBuilder,
+        },
+        Cursor, FindOptions, FlattenLimits, Hit, Order,
+    },
+    key::{key_time, KeyGenerator},
+    query::Query,
+    retention::Retention,
+    shared, storage,
+    timestamp::TimestampExtractor,
+};
 
 pub(crate) type Key = u64;
-pub(crate) type Skip = u64;
 
-pub(crate) type Transmitter = tokio::sync::broadcast::Sender<Arc<Vec<u8>>>;
-pub(crate) type Notifier = tokio::sync::broadcast::Receiver<Arc<Vec<u8>>>;
+pub(crate) type Transmitter = tokio::sync::broadcast::Sender<Batch>;
+pub(crate) type Notifier = tokio::sync::broadcast::Receiver<Batch>;
 
-pub(crate) type LogStoragePointer = Arc<Mutex<LogStorage>>;
+/// Logs which are stored together and broadcasted to subscribers at once.
+pub(crate) type Batch = Arc<Vec<Record>>;
 
+pub(crate) type LogStoragePointer = Arc<LogStorage>;
+
+// Found logs with their data.
+pub(crate) type Hits = Vec<(Hit, Vec<u8>)>;
+
+/// Stored log which is broadcasted to subscribers.
+#[derive(Debug)]
+pub(crate) struct Record {
+    pub(crate) hit: Hit,
+    pub(crate) data: Vec<u8>,
+}
+
+pub(crate) struct SearchResult {
+    pub(crate) hits: Hits,
+    // Number of all matched logs, not only returned ones.
+    pub(crate) total: usize,
+    // Is set if there are more hits after returned ones.
+    pub(crate) cursor: Option<Cursor>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) struct Stats {
+    pub(crate) records: u64,
+    pub(crate) bytes: u64,
+    pub(crate) disk_bytes: u64,
+    // Size of stored data before compression to its size on the disk.
+    pub(crate) compression_ratio: f64,
+    // Logs evicted by retention policy since start.
+    pub(crate) evicted_records: u64,
+    pub(crate) evicted_bytes: u64,
+}
+
+// Index and storage are locked separately and only for a single operation,
+// so searches run concurrently and don't wait for each other, only for writes.
 pub(crate) struct LogStorage {
-    index: index::Index,
-    storage: storage::Storage,
+    index: RwLock<index::Index>,
+    storage: RwLock<storage::Storage>,
+    keys: Mutex<KeyGenerator>,
+    timestamps: TimestampExtractor,
+    // Logs are flattened as in the index to aggregate them without the index.
+    limits: FlattenLimits,
+    evicted: Mutex<storage::Stats>,
+    // Checkpoints are written one by one, so an older snapshot can't overwrite a newer one.
+    checkpoint: tokio::sync::Mutex<()>,
     lst: Transmitter, //log storage transmitter
     // We need to store it in order to not close transmitter channel.
     _lsn: Notifier,
 }
 
 impl LogStorage {
-    pub(crate) fn new(
-        index_name: &str,
-        storage_name: &str,
-    ) -> Result<(Self, Transmitter), Box<dyn std::error::Error>> {
-        let index = index::new_index(index_name)?;
-        let storage = storage::new_storage(storage_name)?;
+    pub(crate) fn new(cfg: &Config) -> Result<(Self, Transmitter), Box<dyn std::error::Error>> {
+        let index = index::new_index(
+            &cfg.index_name,
+            &cfg.index_path,
+            FlattenLimits::new(cfg),
+            index::analyzer::Analyzer::new(cfg),
+        )?;
+        let storage = storage::new_storage(
+            &cfg.storage_name,
+            &cfg.storage_path,
+            cfg.storage_segment_size,
+            &cfg.storage_compression,
+        )?;
         let (tx, rx) = tokio::sync::broadcast::channel(100);
         let mut log_storage = Self {
-            index,
-            storage,
+            index: RwLock::new(index),
+            storage: RwLock::new(storage),
+            keys: Mutex::new(KeyGenerator::new(cfg.node_id)?),
+            timestamps: TimestampExtractor::new(&cfg.timestamp_field, &cfg.timestamp_formats)?,
+            limits: FlattenLimits::new(cfg),
+            evicted: Mutex::new(storage::Stats::default()),
+            checkpoint: tokio::sync::Mutex::new(()),
             lst: tx.clone(),
             _lsn: rx,
         };
@@ -38,45 +105,876 @@ impl LogStorage {
         Ok((log_storage, tx))
     }
 
-    pub(crate) async fn store(&mut self, data: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+    /// Stores logs taking locks once for the whole batch.
+    pub(crate) async fn store_batch(
+        &self,
+        batch: Vec<Vec<u8>>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let batch = {
+            let mut keys = self.keys.lock().map_err(map_err)?;
+            batch.into_iter().map(|data| (keys.next(), data)).collect()
+        };
+        self.store_records(batch).await
+    }
+
+    /// Stores log with the key generated by another cluster node.
+    pub(crate) async fn store_with_key(
+        &self,
+        key: Key,
+        data: Vec<u8>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.store_records(vec![(key, data)]).await
+    }
+
+    async fn store_records(
+        &self,
+        batch: Vec<(Key, Vec<u8>)>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
         async move {
-            let key: Key = fastrand::u64(..);
-            self.storage.write(key, &data)?;
-            self.index.index(key, &data)?;
-            shared::broadcast(&self.lst, Arc::new(data))?;
-            Ok(())
+            if batch.is_empty() {
+                return Ok(());
+            }
+            let mut records: Vec<Record> = batch
+                .into_iter()
+                .map(|(key, data)| Record {
+                    hit: Hit {
+                        timestamp: self.timestamp(key, &data),
+                        key,
+                    },
+                    data,
+                })
+                .collect();
+            let mut res = Ok(());
+            {
+                // Both locks are held, so searches see either the whole batch or nothing.
+                let mut storage = write(&self.storage)?;
+                let mut index = write(&self.index)?;
+                let mut written = 0;
+                for record in &records {
+                    if let Err(e) = storage.write(record.hit.key, &record.data) {
+                        res = Err(e);
+                        break;
+                    }
+                    written += 1;
+                }
+                // Logs written before the failure are kept, so they are indexed and broadcasted too.
+                records.truncate(written);
+                for record in &records {
+                    // One log which index can't handle shouldn't fail the whole batch.
+                    if let Err(e) = index.index(record.hit.key, record.hit.timestamp, &record.data)
+                    {
+                        warn!(key = record.hit.key, "failed to index record: {}", e);
+                    }
+                }
+            }
+            if !records.is_empty() {
+                shared::broadcast(&self.lst, Arc::new(records))?;
+            }
+            Ok(res?)
         }
         .await
     }
 
-    pub(crate) async fn find(
+    pub(crate) async fn search(
         &self,
-        query: &str,
-        skip: Skip,
-    ) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error>> {
+        query: &Query,
+        options: &FindOptions,
+    ) -> Result<SearchResult, Box<dyn std::error::Error>> {
         async move {
-            let mut values: Vec<Vec<u8>> = Vec::new();
-            let keys = match self.index.find(query, skip) {
-                Ok(keys) => keys,
-                Err(e) => match e {
-                    crate::index::error::Error::NotFound => return Ok(values),
-                    _ => return Err(e.to_string().into()),
-                },
+            let found = self.find_hits(query, options)?;
+            let hits = self.read_hits(found.hits)?;
+            Ok(SearchResult {
+                hits,
+                total: found.total,
+                cursor: found.cursor,
+            })
+        }
+        .await
+    }
+
+    /// Aggregates logs matched by the query in the time range of the options.
+    pub(crate) async fn aggregate(
+        &self,
+        query: &Query,
+        options: &FindOptions,
+        aggregation: &Aggregation,
+    ) -> Result<AggregationResult, Box<dyn std::error::Error>> {
+        async move {
+            match read(&self.index)?.aggregate(query, options, aggregation) {
+                Ok(result) => return Ok(result),
+                Err(index::error::Error::AggregationUnsupported(_)) => (),
+                Err(e) => return Err(e.into()),
+            }
+            // Index can't aggregate logs, so we read and decode all matched logs.
+            let options = FindOptions {
+                from: options.from,
+                to: options.to,
+                ..FindOptions::default()
+            };
+            let hits = self.read_hits(self.find_hits(query, &options)?.hits)?;
+            let counts = match aggregation.field() {
+                Some(field) => aggregation::count_values(
+                    field,
+                    hits.iter().map(|(_, data)| data.as_slice()),
+                    &self.limits,
+                ),
+                None => aggregation::ValueCounts::new(),
             };
-            for key in keys {
-                let data = self.storage.read(key)?;
-                values.push(data);
+            Ok(aggregation::aggregate(aggregation, hits.len(), counts))
+        }
+        .await
+    }
+
+    /// Counts logs matched by the query in time buckets.
+    pub(crate) async fn histogram(
+        &self,
+        query: &Query,
+        histogram: &Histogram,
+    ) -> Result<Vec<HistogramBucket>, Box<dyn std::error::Error>> {
+        async move {
+            match read(&self.index)?.histogram(query, histogram) {
+                Ok(buckets) => return Ok(buckets),
+                Err(index::error::Error::AggregationUnsupported(_)) => (),
+                Err(e) => return Err(e.into()),
+            }
+            // Times are taken from the index, logs are read only to split them by field values.
+            let options = FindOptions {
+                from: Some(histogram.from),
+                to: Some(histogram.to),
+                ..FindOptions::default()
+            };
+            let hits = self.find_hits(query, &options)?.hits;
+            let mut builder = HistogramBuilder::new(histogram);
+            let Some(field) = &histogram.split else {
+                hits.iter().for_each(|hit| builder.add(hit.timestamp));
+                return Ok(builder.build());
+            };
+            for (hit, data) in self.read_hits(hits)? {
+                builder.add(hit.timestamp);
+                let Ok(serde_json::Value::Object(obj)) = serde_json::from_slice(&data) else {
+                    continue;
+                };
+                for value in aggregation::field_values(&obj, field, &self.limits) {
+                    builder.add_value(hit.timestamp, &value);
+                }
+            }
+            Ok(builder.build())
+        }
+        .await
+    }
+
+    /// Returns one page of logs with keys greater than the given one ordered by keys,
+    /// so new logs can be followed regardless of their event time.
+    pub(crate) async fn tail(
+        &self,
+        query: &Query,
+        after: Key,
+        limit: usize,
+    ) -> Result<SearchResult, Box<dyn std::error::Error>> {
+        let options = FindOptions {
+            order: Order::Keys,
+            limit,
+            cursor: Some(Cursor::after_key(after)),
+            ..FindOptions::default()
+        };
+        self.search(query, &options).await
+    }
+
+    /// Deletes all logs matched by the query and returns their number.
+    /// Data of deleted logs is removed from the disk before it returns,
+    /// so both storage segments and the index checkpoint are rewritten without it.
+    /// Matched logs are only counted in dry run mode.
+    pub(crate) async fn delete_by_query(
+        &self,
+        query: &Query,
+        dry_run: bool,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        async move {
+            let found = self.find_hits(query, &FindOptions::default())?;
+            if dry_run {
+                return Ok(found.total);
+            }
+            let mut deleted = 0;
+            for hit in &found.hits {
+                // Log can be already deleted concurrently, e.g. by retention.
+                if self.delete(hit.key)?.is_some() {
+                    deleted += 1;
+                }
+            }
+            if deleted > 0 {
+                write(&self.storage)?.purge()?;
+                self.checkpoint().await?;
             }
-            Ok(values)
+            Ok(deleted)
         }
         .await
     }
 
+    /// Evicts the oldest logs while retention limits are exceeded
+    /// and returns the number of evicted logs, but not more than the limit.
+    pub(crate) fn evict(
+        &self,
+        retention: &Retention,
+        limit: usize,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        let now = shared::now_as_nanos_u64()?;
+        let mut evicted = 0;
+        while evicted < limit {
+            let (key, stats) = {
+                let storage = read(&self.storage)?;
+                let Some(key) = storage.first() else {
+                    break;
+                };
+                (key, storage.stats())
+            };
+            if !retention.is_exceeded(key, &stats, now) {
+                break;
+            }
+            let Some(size) = self.delete(key)? else {
+                continue;
+            };
+            let mut stats = self.evicted.lock().map_err(map_err)?;
+            stats.records += 1;
+            stats.bytes += size;
+            evicted += 1;
+        }
+        Ok(evicted)
+    }
+
+    pub(crate) fn stats(&self) -> Result<Stats, Box<dyn std::error::Error>> {
+        let stats = read(&self.storage)?.stats();
+        let evicted = *self.evicted.lock().map_err(map_err)?;
+        Ok(Stats {
+            records: stats.records,
+            bytes: stats.bytes,
+            disk_bytes: stats.disk_bytes,
+            compression_ratio: match stats.disk_bytes {
+                0 => 1.0,
+                disk_bytes => stats.raw_bytes as f64 / disk_bytes as f64,
+            },
+            evicted_records: evicted.records,
+            evicted_bytes: evicted.bytes,
+        })
+    }
+
+    // Deletes log from both index and storage and returns its size
+    // or nothing if it was already deleted.
+    fn delete(&self, key: Key) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+        // Storage is locked for the whole deletion, so log can't be deleted twice.
+        let mut storage = write(&self.storage)?;
+        let data = match storage.read(key) {
+            Ok(data) => data,
+            Err(storage::error::Error::NotFound) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        // Index can fail to handle data which was never indexed, it is fine to skip it.
+        if let Err(e) = write(&self.index)?.delete(key, &data) {
+            warn!(key, "failed to delete record from the index: {}", e);
+        }
+        storage.delete(key)?;
+        Ok(Some(data.len() as u64))
+    }
+
+    // Logs can be deleted after they were found, such logs are skipped.
+    fn read_hits(&self, hits: Vec<Hit>) -> Result<Hits, Box<dyn std::error::Error>> {
+        let storage = read(&self.storage)?;
+        let mut res: Hits = Vec::with_capacity(hits.len());
+        for hit in hits {
+            match storage.read(hit.key) {
+                Ok(data) => res.push((hit, data)),
+                Err(storage::error::Error::NotFound) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(res)
+    }
+
+    // Logs without event time get the time when they were received.
+    // Key contains this time, so replicas and restored logs get the same time.
+    fn timestamp(&self, key: Key, data: &[u8]) -> u64 {
+        self.timestamps.extract(data).unwrap_or_else(|| key_time(key))
+    }
+
+    /// Returns receiver of all new logs.
+    pub(crate) fn subscribe(&self) -> Notifier {
+        self.lst.subscribe()
+    }
+
+    fn find_hits(
+        &self,
+        query: &Query,
+        options: &FindOptions,
+    ) -> Result<index::FindResult, Box<dyn std::error::Error>> {
+        match read(&self.index)?.find(query, options) {
+            Ok(found) => Ok(found),
+            Err(index::error::Error::NotFound) => Ok(index::FindResult::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Persists the index, so it is not rebuilt from the storage on restart.
+    /// Index is locked only to take its snapshot, which is written on a blocking thread.
+    pub(crate) async fn checkpoint(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let _checkpoint = self.checkpoint.lock().await;
+        let Some(snapshot) = read(&self.index)?.snapshot()? else {
+            return Ok(());
+        };
+        tokio::task::spawn_blocking(move || snapshot.write()).await??;
+        Ok(())
+    }
+
+    // Index can be loaded from the checkpoint, so we index only logs stored after it
+    // and forget logs deleted after it.
     fn restore(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let entries = self.storage.list()?;
-        for entry in entries {
-            self.index.index(entry.0, &entry.1)?;
+        let index = self.index.get_mut().map_err(map_err)?;
+        let mut forgotten = index.keys()?;
+        if forgotten.is_empty() {
+            return self.restore_all();
         }
+        let storage = self.storage.get_mut().map_err(map_err)?;
+        let keys = self.keys.get_mut().map_err(map_err)?;
+        let mut restored = 0;
+        for key in storage.keys().collect::<Vec<Key>>() {
+            keys.advance(key);
+            if forgotten.remove(&key) {
+                continue;
+            }
+            let data = storage.read(key)?;
+            let timestamp = self.timestamps.extract(&data).unwrap_or_else(|| key_time(key));
+            restore_record(index, key, timestamp, &data);
+            restored += 1;
+        }
+        index.forget(&forgotten)?;
+        info!(restored, forgotten = forgotten.len(), "index is restored from the checkpoint");
+        Ok(())
+    }
+
+    // Reads all logs sequentially as it is faster than reading them one by one.
+    fn restore_all(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let index = self.index.get_mut().map_err(map_err)?;
+        let storage = self.storage.get_mut().map_err(map_err)?;
+        let keys = self.keys.get_mut().map_err(map_err)?;
+        let mut restored = 0;
+        for entry in storage.list()? {
+            let (key, data) = entry?;
+            keys.advance(key);
+            // Fields are borrowed separately, so we can't use timestamp method.
+            let timestamp = self.timestamps.extract(&data).unwrap_or_else(|| key_time(key));
+            restore_record(index, key, timestamp, &data);
+            restored += 1;
+        }
+        info!(restored, "index is restored from the storage");
         Ok(())
     }
 }
+
+fn read<T>(lock: &RwLock<T>) -> Result<RwLockReadGuard<'_, T>, Box<dyn std::error::Error>> {
+    lock.read().map_err(map_err)
+}
+
+fn write<T>(lock: &RwLock<T>) -> Result<RwLockWriteGuard<'_, T>, Box<dyn std::error::Error>> {
+    lock.write().map_err(map_err)
+}
+
+// Lock is poisoned only if another thread panicked while holding it.
+fn map_err<E: std::fmt::Display>(e: E) -> Box<dyn std::error::Error> {
+    e.to_string().into()
+}
+
+fn restore_record(index: &mut index::Index, key: Key, timestamp: u64, data: &[u8]) {
+    // Storage can contain data which index was failed to handle,
+    // we don't want to stop the whole restore because of it.
+    if let Err(e) = index.index(key, timestamp, data) {
+        warn!(key, "failed to restore record in the index: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::Config,
+        index::{
+            value::{Number, Value},
+            Cursor, FindOptions, Order,
+        },
+        log_storage::*,
+        query,
+        retention::Retention,
+    };
+
+    #[tokio::test]
+    async fn test_search() {
+        let cfg = config();
+        let (log_storage, _) = LogStorage::new(&cfg).unwrap();
+        for i in 0..5 {
+            let data = format!(r#"{{"level":"debug","id":{}}}"#, i);
+            log_storage.store_batch(vec![data.into_bytes()]).await.unwrap();
+            // Logs should have different times to check time range.
+            tokio::time::sleep(tokio::time::Duration::from_millis(2)).await;
+        }
+        log_storage.store_batch(vec![br#"{"level":"info"}"#.to_vec()]).await.unwrap();
+        let query = query::parse("level:debug").unwrap();
+
+        let search = |order: Order, limit: usize, cursor: Option<Cursor>| FindOptions {
+            order,
+            limit,
+            cursor,
+            ..FindOptions::default()
+        };
+        let ids = |result: &SearchResult| -> Vec<u64> {
+            result
+                .hits
+                .iter()
+                .map(|(_, data)| {
+                    let value: serde_json::Value = serde_json::from_slice(data).unwrap();
+                    value["id"].as_u64().unwrap()
+                })
+                .collect()
+        };
+
+        let mut cursor = None;
+        for expected in [vec![0, 1], vec![2, 3], vec![4]] {
+            let result = log_storage.search(&query, &search(Order::Asc, 2, cursor)).await.unwrap();
+            assert_eq!(5, result.total);
+            assert_eq!(expected, ids(&result));
+            cursor = result.cursor;
+        }
+        assert!(cursor.is_none());
+
+        let result = log_storage.search(&query, &search(Order::Desc, 3, None)).await.unwrap();
+        assert_eq!(vec![4, 3, 2], ids(&result));
+        let cursor: Cursor = result.cursor.unwrap().to_string().parse().unwrap();
+        let result =
+            log_storage.search(&query, &search(Order::Desc, 3, Some(cursor))).await.unwrap();
+        assert_eq!(vec![1, 0], ids(&result));
+        assert!(result.cursor.is_none());
+
+        // Time range includes its lower bound and excludes the upper one.
+        let result = log_storage.search(&query, &search(Order::Asc, 10, None)).await.unwrap();
+        let timestamps: Vec<u64> = result.hits.iter().map(|(hit, _)| hit.timestamp).collect();
+        let options = FindOptions {
+            from: Some(timestamps[1]),
+            to: Some(timestamps[4]),
+            ..search(Order::Asc, 10, None)
+        };
+        let result = log_storage.search(&query, &options).await.unwrap();
+        assert_eq!(3, result.total);
+        assert_eq!(vec![1, 2, 3], ids(&result));
+    }
+
+    #[tokio::test]
+    async fn test_aggregate() {
+        check_aggregate(&config()).await;
+        // Tantivy index doesn't aggregate logs, so they are aggregated from the storage.
+        #[cfg(feature = "index_tantivy")]
+        check_aggregate(&Config {
+            index_name: "tantivy".to_string(),
+            ..config()
+        })
+        .await;
+    }
+
+    async fn check_aggregate(cfg: &Config) {
+        let (log_storage, _) = LogStorage::new(cfg).unwrap();
+        let logs = [
+            r#"{"level":"error","component":"api","latency":100}"#,
+            r#"{"level":"error","component":"api","latency":300}"#,
+            r#"{"level":"error","component":"db","latency":200.5}"#,
+            r#"{"level":"info","component":"api","latency":1}"#,
+            r#"{"level":"error","component":["db","api","api"],"latency":"slow"}"#,
+        ];
+        log_storage
+            .store_batch(logs.iter().map(|x| x.as_bytes().to_vec()).collect())
+            .await
+            .unwrap();
+        let aggregate = |query: &str, aggregation: Aggregation| {
+            let log_storage = &log_storage;
+            let query = query::parse(query).unwrap();
+            async move {
+                log_storage.aggregate(&query, &FindOptions::default(), &aggregation).await.unwrap()
+            }
+        };
+        let string = |x: &str| Value::String(x.to_string());
+        let integer = |x: i128| Value::Number(Number::Integer(x));
+        let field = "latency".to_string();
+
+        assert_eq!(AggregationResult::Count(4), aggregate("level:error", Aggregation::Count).await);
+        assert_eq!(
+            AggregationResult::Count(0),
+            aggregate("level:unknown", Aggregation::Count).await
+        );
+        // Query error is returned instead of aggregating logs from the storage.
+        let query = query::parse(r#""!!!""#).unwrap();
+        let err = log_storage
+            .aggregate(&query, &FindOptions::default(), &Aggregation::Count)
+            .await
+            .unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(index::error::Error::Unsupported(_))));
+        // Log is counted once for every value of the array.
+        let terms = |size: usize| Aggregation::Terms {
+            field: "component".to_string(),
+            size,
+        };
+        assert_eq!(
+            AggregationResult::Terms {
+                count: 4,
+                buckets: vec![(string("api"), 3), (string("db"), 2)],
+                other: 0,
+            },
+            aggregate("level:error", terms(10)).await
+        );
+        assert_eq!(
+            AggregationResult::Terms {
+                count: 4,
+                buckets: vec![(string("api"), 3)],
+                other: 2,
+            },
+            aggregate("level:error", terms(1)).await
+        );
+        // Only numeric values are aggregated.
+        assert_eq!(
+            AggregationResult::Stats {
+                count: 4,
+                stats: Some(aggregation::Stats {
+                    values: 3,
+                    min: integer(100),
+                    max: integer(300),
+                    sum: 600.5,
+                    avg: 600.5 / 3.0,
+                }),
+            },
+            aggregate(
+                "level:error",
+                Aggregation::Stats {
+                    field: field.clone()
+                }
+            )
+            .await
+        );
+        assert_eq!(
+            AggregationResult::Stats {
+                count: 1,
+                stats: None,
+            },
+            aggregate(
+                "level:error AND component:db AND NOT latency>0",
+                Aggregation::Stats {
+                    field: field.clone()
+                }
+            )
+            .await
+        );
+        assert_eq!(
+            AggregationResult::Percentiles {
+                count: 4,
+                percentiles: vec![
+                    (0.0, Some(integer(100))),
+                    (50.0, Some(Value::Number(Number::Float(200.5)))),
+                    (100.0, Some(integer(300))),
+                ],
+            },
+            aggregate(
+                "level:error",
+                Aggregation::Percentiles {
+                    field,
+                    percents: vec![0.0, 50.0, 100.0],
+                }
+            )
+            .await
+        );
+
+        let options = FindOptions {
+            from: Some(shared::now_as_nanos_u64().unwrap() + 1_000_000_000),
+            ..FindOptions::default()
+        };
+        let query = query::parse("level:error").unwrap();
+        let result = log_storage.aggregate(&query, &options, &Aggregation::Count).await.unwrap();
+        assert_eq!(AggregationResult::Count(0), result);
+    }
+
+    #[tokio::test]
+    async fn test_histogram() {
+        let cfg = Config {
+            timestamp_field: "time".to_string(),
+            timestamp_formats: "unix_s".to_string(),
+            ..config()
+        };
+        check_histogram(&cfg).await;
+        // Tantivy index doesn't split logs by values, so they are read from the storage.
+        #[cfg(feature = "index_tantivy")]
+        check_histogram(&Config {
+            index_name: "tantivy".to_string(),
+            ..cfg
+        })
+        .await;
+    }
+
+    async fn check_histogram(cfg: &Config) {
+        let (log_storage, _) = LogStorage::new(cfg).unwrap();
+        let logs = [
+            r#"{"time":100,"level":"error"}"#,
+            r#"{"time":130,"level":"info"}"#,
+            r#"{"time":170,"level":["error","warn"]}"#,
+            r#"{"time":250}"#,
+            // Logs outside of the time range are not counted.
+            r#"{"time":10,"level":"error"}"#,
+            r#"{"time":300,"level":"error"}"#,
+        ];
+        log_storage
+            .store_batch(logs.iter().map(|x| x.as_bytes().to_vec()).collect())
+            .await
+            .unwrap();
+        let seconds = |x: u64| x * 1_000_000_000;
+        let mut histogram = Histogram {
+            from: seconds(100),
+            to: seconds(300),
+            interval: seconds(60),
+            split: None,
+        };
+        let query = query::parse("NOT level:debug").unwrap();
+        let buckets = log_storage.histogram(&query, &histogram).await.unwrap();
+        // Buckets are aligned to the interval and empty buckets are returned too.
+        let counts: Vec<(u64, usize)> = buckets.iter().map(|x| (x.start, x.count)).collect();
+        assert_eq!(
+            vec![
+                (seconds(60), 1),
+                (seconds(120), 2),
+                (seconds(180), 0),
+                (seconds(240), 1)
+            ],
+            counts
+        );
+        assert!(buckets.iter().all(|x| x.split.is_empty()));
+
+        histogram.split = Some("level".to_string());
+        let buckets = log_storage.histogram(&query, &histogram).await.unwrap();
+        let string = |x: &str| Value::String(x.to_string());
+        let split: Vec<Vec<(Value, usize)>> =
+            buckets.into_iter().map(|x| x.split.into_iter().collect()).collect();
+        assert_eq!(
+            vec![
+                vec![(string("error"), 1)],
+                vec![
+                    (string("error"), 1),
+                    (string("info"), 1),
+                    (string("warn"), 1)
+                ],
+                vec![],
+                vec![],
+            ],
+            split
+        );
+    }
+
+    #[tokio::test]
+    async fn test_evict() {
+        let cfg = Config {
+            retention_max_records: 3,
+            retention_interval: 1,
+            ..config()
+        };
+        let retention = Retention::new(&cfg).unwrap();
+        let (log_storage, _) = LogStorage::new(&cfg).unwrap();
+        for i in 0..5 {
+            let data = format!(r#"{{"level":"debug","id":{}}}"#, i);
+            log_storage.store_batch(vec![data.into_bytes()]).await.unwrap();
+        }
+        assert_eq!(1, log_storage.evict(&retention, 1).unwrap());
+        assert_eq!(1, log_storage.evict(&retention, 10).unwrap());
+        assert_eq!(0, log_storage.evict(&retention, 10).unwrap());
+
+        let stats = log_storage.stats().unwrap();
+        assert_eq!((3, 2), (stats.records, stats.evicted_records));
+        assert_eq!(stats.evicted_bytes * 3, stats.bytes * 2);
+        // Evicted logs are removed from the index too.
+        let query = query::parse("level:debug").unwrap();
+        let result = log_storage.search(&query, &FindOptions::default()).await.unwrap();
+        assert_eq!(3, result.total);
+        assert!(result
+            .hits
+            .iter()
+            .all(|(_, data)| !data.ends_with(b"0}") && !data.ends_with(b"1}")));
+    }
+
+    #[tokio::test]
+    async fn test_tail() {
+        let cfg = Config {
+            timestamp_field: "time".to_string(),
+            timestamp_formats: "unix_s".to_string(),
+            ..config()
+        };
+        let (log_storage, _) = LogStorage::new(&cfg).unwrap();
+        // Event time is in reverse order of keys, but logs are tailed by keys.
+        for timestamp in [3, 2, 1] {
+            let data = format!(r#"{{"level":"debug","time":{}}}"#, timestamp);
+            log_storage.store_batch(vec![data.into_bytes()]).await.unwrap();
+        }
+        let query = query::parse("level:debug").unwrap();
+        let result = log_storage.tail(&query, 0, 2).await.unwrap();
+        let keys: Vec<Key> = result.hits.iter().map(|(hit, _)| hit.key).collect();
+        assert_eq!(2, keys.len());
+        assert!(keys[0] < keys[1]);
+        let cursor = result.cursor.unwrap();
+        assert_eq!(keys[1], cursor.key());
+        let result = log_storage.tail(&query, cursor.key(), 2).await.unwrap();
+        assert_eq!(1, result.hits.len());
+        assert!(result.hits[0].0.key > keys[1]);
+        assert!(result.cursor.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_delete_by_query() {
+        let (log_storage, _) = LogStorage::new(&config()).unwrap();
+        for level in ["debug", "info", "debug"] {
+            let data = format!(r#"{{"level":"{}"}}"#, level);
+            log_storage.store_batch(vec![data.into_bytes()]).await.unwrap();
+        }
+        let query = query::parse("level:debug").unwrap();
+        assert_eq!(2, log_storage.delete_by_query(&query, true).await.unwrap());
+        assert_eq!(3, log_storage.stats().unwrap().records);
+        assert_eq!(2, log_storage.delete_by_query(&query, false).await.unwrap());
+        assert_eq!(0, log_storage.delete_by_query(&query, false).await.unwrap());
+        let stats = log_storage.stats().unwrap();
+        assert_eq!((1, 0), (stats.records, stats.evicted_records));
+        let query = query::parse("NOT level:debug").unwrap();
+        assert_eq!(1, log_storage.search(&query, &FindOptions::default()).await.unwrap().total);
+    }
+
+    #[tokio::test]
+    async fn test_restore() {
+        let path = std::env::temp_dir().join(format!("loghell-test-{}", fastrand::u64(..)));
+        let cfg = Config {
+            storage_name: "file".to_string(),
+            storage_path: path.join("storage").to_str().unwrap().to_string(),
+            storage_segment_size: 1024,
+            index_path: path.join("index").to_str().unwrap().to_string(),
+            ..config()
+        };
+        let (log_storage, _) = LogStorage::new(&cfg).unwrap();
+        for level in ["debug", "info", "debug"] {
+            let data = format!(r#"{{"level":"{}"}}"#, level);
+            log_storage.store_batch(vec![data.into_bytes()]).await.unwrap();
+        }
+        log_storage.checkpoint().await.unwrap();
+        // Logs are stored and deleted after the checkpoint.
+        log_storage.store_batch(vec![br#"{"level":"warn"}"#.to_vec()]).await.unwrap();
+        let query = query::parse("level:info").unwrap();
+        assert_eq!(1, log_storage.delete_by_query(&query, false).await.unwrap());
+        // Deleted log is purged from both storage segments and the index checkpoint.
+        for dir in ["storage", "index"] {
+            for entry in std::fs::read_dir(path.join(dir)).unwrap() {
+                let data = std::fs::read(entry.unwrap().path()).unwrap();
+                assert!(!data.windows(4).any(|x| x == b"info"), "{} has deleted log", dir);
+            }
+        }
+        drop(log_storage);
+
+        let (log_storage, _) = LogStorage::new(&cfg).unwrap();
+        for (query, total) in [("level:debug", 2), ("level:info", 0), ("level:warn", 1)] {
+            let query = query::parse(query).unwrap();
+            let result = log_storage.search(&query, &FindOptions::default()).await.unwrap();
+            assert_eq!(total, result.total, "query: {:?}", query);
+        }
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_partial_batch() {
+        let path = std::env::temp_dir().join(format!("loghell-test-{}", fastrand::u64(..)));
+        let cfg = Config {
+            storage_name: "file".to_string(),
+            storage_path: path.to_str().unwrap().to_string(),
+            storage_segment_size: 16,
+            ..config()
+        };
+        let (log_storage, tx) = LogStorage::new(&cfg).unwrap();
+        let mut rx = tx.subscribe();
+        // The second log starts a new segment, which can't be created without the directory.
+        std::fs::remove_dir_all(&path).unwrap();
+        let batch = vec![
+            br#"{"level":"debug","id":1}"#.to_vec(),
+            br#"{"level":"debug","id":2}"#.to_vec(),
+        ];
+        assert!(log_storage.store_batch(batch).await.is_err());
+
+        // The written log is indexed and broadcasted, its segment can't be read anymore.
+        let query = query::parse("level:debug AND id:1").unwrap();
+        assert_eq!(1, log_storage.find_hits(&query, &FindOptions::default()).unwrap().total);
+        assert_eq!(1, log_storage.stats().unwrap().records);
+        let records = rx.try_recv().unwrap();
+        assert_eq!(
+            vec![br#"{"level":"debug","id":1}"#.to_vec()],
+            records.iter().map(|x| x.data.clone()).collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_access() {
+        let log_storage = Arc::new(LogStorage::new(&config()).unwrap().0);
+        let query = query::parse("level:debug").unwrap();
+        let mut handles = Vec::new();
+        for writer in 0..4 {
+            let log_storage = log_storage.clone();
+            handles.push(tokio::spawn(async move {
+                for batch in 0..25 {
+                    let batch = (0..10)
+                        .map(|i| {
+                            format!(
+                                r#"{{"level":"debug","writer":{},"id":{}}}"#,
+                                writer,
+                                batch * 10 + i
+                            )
+                            .into_bytes()
+                        })
+                        .collect();
+                    log_storage.store_batch(batch).await.unwrap();
+                }
+            }));
+        }
+        for _ in 0..4 {
+            let log_storage = log_storage.clone();
+            let query = query.clone();
+            handles.push(tokio::spawn(async move {
+                for _ in 0..50 {
+                    let result = log_storage.search(&query, &FindOptions::default()).await.unwrap();
+                    assert_eq!(result.total, result.hits.len());
+                }
+            }));
+        }
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        let result = log_storage.search(&query, &FindOptions::default()).await.unwrap();
+        assert_eq!(1000, result.total);
+        assert_eq!(1000, log_storage.stats().unwrap().records);
+    }
+
+    fn config() -> Config {
+        Config {
+            socket_addr: String::new(),
+            index_name: "nonsense".to_string(),
+            index_path: String::new(),
+            index_max_depth: 0,
+            index_max_fields: 0,
+            index_checkpoint_interval: 0,
+            text_fields: "message".to_string(),
+            text_stemming: false,
+            text_stop_words: String::new(),
+            storage_name: "in_memory".to_string(),
+            cluster_addrs: String::new(),
+            node_id: 0,
+            storage_path: String::new(),
+            storage_segment_size: 0,
+            storage_compression: "none".to_string(),
+            ingest_framing: String::new(),
+            ingest_format: String::new(),
+            ingest_max_record_size: 0,
+            timestamp_field: String::new(),
+            timestamp_formats: String::new(),
+            retention_max_age: 0,
+            retention_max_bytes: 0,
+            retention_max_records: 0,
+            retention_interval: 0,
+        }
+    }
+}
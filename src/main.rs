@@ -1,20 +1,25 @@
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
-use tokio::sync::Mutex;
 use tokio::task::JoinHandle;
 use tracing::{debug, error, info, trace};
 use tracing_subscriber::filter::{LevelFilter, Targets};
 use tracing_subscriber::fmt;
 use tracing_subscriber::layer::SubscriberExt;
 
+mod checkpoint;
 mod cluster;
 mod config;
 mod index;
+mod key;
 mod log_storage;
+mod parser;
+mod query;
+mod retention;
 mod server;
 mod shared;
 mod storage;
+mod timestamp;
 
 #[repr(u8)]
 #[derive(PartialEq, Eq)]
@@ -34,7 +39,7 @@ impl From<ExitCode> for std::process::ExitCode {
 async fn main() -> Result<std::process::ExitCode, Box<dyn std::error::Error>> {
     let dashboard_content = include_str!("../dashboard/index.html");
 
-    let cfg = config::Config::new();
+    let cfg = config::Config::new()?;
 
     let filter =
         Targets::new().with_target("loghell", tracing::Level::TRACE).with_default(LevelFilter::OFF);
@@ -42,8 +47,10 @@ async fn main() -> Result<std::process::ExitCode, Box<dyn std::error::Error>> {
     let subscriber = tracing_subscriber::registry().with(filter).with(terminal_subscriber);
     tracing::subscriber::set_global_default(subscriber).expect("failed to set global subscriber");
 
-    let (log_storage, lst) = log_storage::LogStorage::new(&cfg.index_name, &cfg.storage_name)?;
-    let log_storage = Arc::new(Mutex::new(log_storage));
+    let retention = retention::Retention::new(&cfg)?;
+    let checkpointer = checkpoint::Checkpointer::new(&cfg)?;
+    let (log_storage, lst) = log_storage::LogStorage::new(&cfg)?;
+    let log_storage = Arc::new(log_storage);
 
     // csr - cluster state reader.
     let (cluster, csr) = cluster::Cluster::new();
@@ -54,7 +61,12 @@ async fn main() -> Result<std::process::ExitCode, Box<dyn std::error::Error>> {
         connection_counter.clone(),
         log_storage.clone(),
         csr,
-    );
+        &cfg.ingest_framing,
+        &cfg.ingest_format,
+        cfg.ingest_max_record_size,
+        index::FlattenLimits::new(&cfg),
+        index::analyzer::Analyzer::new(&cfg),
+    )?;
     let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(());
 
     let mut handlers: Vec<JoinHandle<ExitCode>> = vec![];
@@ -75,6 +87,38 @@ async fn main() -> Result<std::process::ExitCode, Box<dyn std::error::Error>> {
     });
     handlers.push(res);
 
+    let log_storage_ = log_storage.clone();
+    let shutdown_rx_ = shutdown_rx.clone();
+    let res: JoinHandle<ExitCode> = tokio::spawn(async move {
+        match retention.start(log_storage_, shutdown_rx_).await {
+            Ok(()) => {
+                debug!("retention has been stopped successfully");
+                ExitCode::Ok
+            }
+            Err(e) => {
+                error!("failed to enforce retention: {}", e);
+                ExitCode::FailedToStartDaemon
+            }
+        }
+    });
+    handlers.push(res);
+
+    let log_storage_ = log_storage.clone();
+    let shutdown_rx_ = shutdown_rx.clone();
+    let res: JoinHandle<ExitCode> = tokio::spawn(async move {
+        match checkpointer.start(log_storage_, shutdown_rx_).await {
+            Ok(()) => {
+                debug!("index checkpoints have been stopped successfully");
+                ExitCode::Ok
+            }
+            Err(e) => {
+                error!("failed to save index checkpoint: {}", e);
+                ExitCode::FailedToStopDaemon
+            }
+        }
+    });
+    handlers.push(res);
+
     let res: JoinHandle<ExitCode> = tokio::spawn(async move {
         match cluster.start(cfg.cluster_addrs, log_storage, lst.subscribe(), shutdown_rx).await {
             Ok(()) => {
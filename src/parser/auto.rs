@@ -0,0 +1,26 @@
+use crate::index;
+use crate::parser::{_Parser, error::Error, json, logfmt, raw::Raw, syslog};
+
+/// Detects format of every log, logs of unknown formats are stored as raw text.
+pub(super) struct Auto;
+
+impl _Parser for Auto {
+    fn parse(&self, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+        let line = data.trim_ascii_start();
+        if line.starts_with(b"{") && index::validate(&data).is_ok() {
+            return Ok(json::remove_line_breaks(data));
+        }
+        if syslog::is_syslog(line) {
+            if let Ok(log) = syslog::parse(&data) {
+                return Ok(log);
+            }
+        }
+        if logfmt::is_logfmt(line) {
+            if let Ok(log) = logfmt::parse(&data) {
+                return Ok(log);
+            }
+        }
+        // Line can only look like some format, e.g. be a broken JSON.
+        Raw.parse(data)
+    }
+}
@@ -0,0 +1,13 @@
+use thiserror::Error;
+
+use crate::index;
+
+#[derive(Error, Debug)]
+pub(crate) enum Error {
+    #[error("unknown parser type: {0}")]
+    UnknownParserType(String),
+    #[error(transparent)]
+    DecodeData(#[from] index::error::Error),
+    #[error("failed to parse data: {0}")]
+    Parse(String),
+}
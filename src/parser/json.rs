@@ -0,0 +1,19 @@
+use crate::index;
+use crate::parser::{_Parser, error::Error};
+
+/// Keeps JSON objects as they are, only line breaks are removed.
+pub(super) struct Json;
+
+impl _Parser for Json {
+    fn parse(&self, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+        index::validate(&data)?;
+        Ok(remove_line_breaks(data))
+    }
+}
+
+// Logs are separated by new lines in cluster messages, so pretty printed JSON is joined.
+// Line breaks can be only whitespace between tokens in valid JSON, strings have them escaped.
+pub(super) fn remove_line_breaks(mut data: Vec<u8>) -> Vec<u8> {
+    data.retain(|x| *x != b'\n' && *x != b'\r');
+    data
+}
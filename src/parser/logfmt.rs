@@ -0,0 +1,88 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use serde_json::Value;
+
+use crate::parser::{_Parser, encode, error::Error, to_str};
+
+/// Parses key=value pairs separated by spaces, values with spaces are quoted.
+pub(super) struct Logfmt;
+
+impl _Parser for Logfmt {
+    fn parse(&self, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+        parse(&data)
+    }
+}
+
+pub(super) fn parse(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let line = to_str(data)?;
+    let mut log = serde_json::Map::new();
+    let mut chars = line.trim().chars().peekable();
+    while chars.peek().is_some() {
+        let mut key = String::new();
+        while let Some(x) = chars.next_if(|x| *x != '=' && *x != ' ') {
+            key.push(x);
+        }
+        if key.is_empty() || key.contains('"') {
+            return Err(Error::Parse(format!("logfmt key is invalid: {:?}", key)));
+        }
+        // Key without value is a flag.
+        let value = match chars.next_if_eq(&'=') {
+            Some(_) => parse_value(&mut chars)?,
+            None => Value::Bool(true),
+        };
+        log.insert(key, value);
+        while chars.next_if_eq(&' ').is_some() {}
+    }
+    if log.is_empty() {
+        return Err(Error::Parse("logfmt line is empty".to_string()));
+    }
+    encode(&log)
+}
+
+/// Returns true if the line starts with a key=value pair.
+pub(super) fn is_logfmt(line: &[u8]) -> bool {
+    let key_length = line
+        .iter()
+        .take_while(|x| x.is_ascii_alphanumeric() || matches!(x, b'_' | b'.' | b'-'))
+        .count();
+    key_length > 0 && line.get(key_length) == Some(&b'=')
+}
+
+fn parse_value(chars: &mut Peekable<Chars>) -> Result<Value, Error> {
+    if chars.next_if_eq(&'"').is_none() {
+        let mut value = String::new();
+        while let Some(x) = chars.next_if(|x| *x != ' ') {
+            value.push(x);
+        }
+        return Ok(typed_value(value));
+    }
+    // Quoted values are always strings.
+    let mut value = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(Value::String(value)),
+            Some('\\') => match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some(x) => value.push(x),
+                None => break,
+            },
+            Some(x) => value.push(x),
+            None => break,
+        }
+    }
+    Err(Error::Parse("logfmt quoted value is not closed".to_string()))
+}
+
+// Numbers and booleans keep their types, so they can be compared in queries.
+fn typed_value(value: String) -> Value {
+    match value.as_str() {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ => match value.parse::<serde_json::Number>() {
+            Ok(number) => Value::Number(number),
+            Err(_) => Value::String(value),
+        },
+    }
+}
@@ -0,0 +1,124 @@
+use error::Error;
+use parser_type::ParserType;
+
+mod auto;
+pub(crate) mod error;
+mod json;
+mod logfmt;
+mod parser_type;
+mod raw;
+mod syslog;
+
+pub(crate) type Parser = Box<dyn _Parser + Send + Sync>;
+
+// Raw text is stored in this field as well as messages of syslog logs.
+const MESSAGE_FIELD: &str = "message";
+
+pub(crate) trait _Parser {
+    // Converts received log to JSON object which is stored and indexed.
+    fn parse(&self, data: Vec<u8>) -> Result<Vec<u8>, Error>;
+}
+
+pub(crate) fn new_parser(parser_name: &str) -> Result<Parser, Error> {
+    let parser_type: ParserType = parser_name.into();
+    let parser: Parser = match parser_type {
+        ParserType::Json => Box::new(json::Json),
+        ParserType::Logfmt => Box::new(logfmt::Logfmt),
+        ParserType::Syslog => Box::new(syslog::Syslog),
+        ParserType::Raw => Box::new(raw::Raw),
+        ParserType::Auto => Box::new(auto::Auto),
+        ParserType::Unknown => return Err(Error::UnknownParserType(parser_name.to_string())),
+    };
+    Ok(parser)
+}
+
+fn to_str(data: &[u8]) -> Result<&str, Error> {
+    std::str::from_utf8(data).map_err(|e| Error::Parse(e.to_string()))
+}
+
+fn encode(log: &serde_json::Map<String, serde_json::Value>) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(log).map_err(|e| Error::Parse(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::*;
+
+    #[test]
+    fn test_json() {
+        let parser = new_parser("json").unwrap();
+        assert_eq!(br#"{"a":1}"#.to_vec(), parse(&parser, r#"{"a":1}"#));
+        assert_eq!(br#"{ "a": "b\nc"}"#.to_vec(), parse(&parser, "{\r\n \"a\": \"b\\nc\"\n}"));
+        assert!(parser.parse(b"[1]".to_vec()).is_err());
+        assert!(parser.parse(b"a=1".to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_logfmt() {
+        let parser = new_parser("logfmt").unwrap();
+        let log =
+            parse(&parser, r#"level=info  msg="a \"b\" c" status=500 ok=true debug id=007 e="#);
+        assert_eq!(
+            r#"{"debug":true,"e":"","id":"007","level":"info","msg":"a \"b\" c","ok":true,"status":500}"#,
+            String::from_utf8(log).unwrap()
+        );
+        assert!(parser.parse(br#"msg="not closed"#.to_vec()).is_err());
+        assert!(parser.parse(b"=1".to_vec()).is_err());
+        assert!(parser.parse(b"a=1 \"b\"".to_vec()).is_err());
+        assert!(parser.parse(b" ".to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_syslog() {
+        let parser = new_parser("syslog").unwrap();
+        let cases: [(&str, &str); 4] = [
+            (
+                r#"<165>1 2003-10-11T22:14:15.003Z host app 12 ID47 [a@1 x="1" y="\"]"][b@2] msg"#,
+                r#"{"app_name":"app","facility":20,"hostname":"host","level":"notice","message":"msg","msg_id":"ID47","proc_id":"12","severity":5,"structured_data":{"a@1":{"x":"1","y":"\"]"},"b@2":{}},"time":"2003-10-11T22:14:15.003Z","version":1}"#,
+            ),
+            ("<13>1 - - - - - -", r#"{"facility":1,"level":"notice","severity":5,"version":1}"#),
+            (
+                "<34>Oct 11 22:14:15 mymachine su[12]: 'su root' failed",
+                r#"{"app_name":"su","facility":4,"hostname":"mymachine","level":"critical","message":"'su root' failed","proc_id":"12","severity":2,"time":"Oct 11 22:14:15"}"#,
+            ),
+            (
+                "<11>message without header",
+                r#"{"facility":1,"level":"error","message":"message without header","severity":3}"#,
+            ),
+        ];
+        for (line, log) in cases {
+            assert_eq!(log, String::from_utf8(parse(&parser, line)).unwrap(), "{}", line);
+        }
+        for line in [
+            "<192>1 - - - - - -",
+            "<a>msg",
+            "<13>1 - - -",
+            "<13>1 - - - - - [a x=1]",
+        ] {
+            assert!(parser.parse(line.as_bytes().to_vec()).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_auto() {
+        let parser = new_parser("auto").unwrap();
+        let cases: [(&str, &str); 5] = [
+            (r#"{"a":1}"#, r#"{"a":1}"#),
+            ("<11>msg", r#"{"facility":1,"level":"error","message":"msg","severity":3}"#),
+            ("a=1 b=c", r#"{"a":1,"b":"c"}"#),
+            (r#"{"a":"#, r#"{"message":"{\"a\":"}"#),
+            (
+                r#"127.0.0.1 - - "GET / HTTP/1.1" 200"#,
+                r#"{"message":"127.0.0.1 - - \"GET / HTTP/1.1\" 200"}"#,
+            ),
+        ];
+        for (line, log) in cases {
+            assert_eq!(log, String::from_utf8(parse(&parser, line)).unwrap(), "{}", line);
+        }
+        assert!(new_parser("xml").is_err());
+    }
+
+    fn parse(parser: &Parser, line: &str) -> Vec<u8> {
+        parser.parse(line.as_bytes().to_vec()).unwrap()
+    }
+}
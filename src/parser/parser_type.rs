@@ -0,0 +1,45 @@
+use std::fmt::{Display, Formatter};
+
+const UNKNOWN: &str = "unknown";
+const JSON: &str = "json";
+const LOGFMT: &str = "logfmt";
+const SYSLOG: &str = "syslog";
+const RAW: &str = "raw";
+const AUTO: &str = "auto";
+
+pub(crate) enum ParserType {
+    Unknown,
+    Json,
+    Logfmt,
+    Syslog,
+    Raw,
+    // Format is detected for every log.
+    Auto,
+}
+
+impl Display for ParserType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            ParserType::Unknown => UNKNOWN.to_string(),
+            ParserType::Json => JSON.to_string(),
+            ParserType::Logfmt => LOGFMT.to_string(),
+            ParserType::Syslog => SYSLOG.to_string(),
+            ParserType::Raw => RAW.to_string(),
+            ParserType::Auto => AUTO.to_string(),
+        };
+        write!(f, "{}", str)
+    }
+}
+
+impl From<&str> for ParserType {
+    fn from(str: &str) -> Self {
+        match str {
+            JSON => ParserType::Json,
+            LOGFMT => ParserType::Logfmt,
+            SYSLOG => ParserType::Syslog,
+            RAW => ParserType::Raw,
+            AUTO => ParserType::Auto,
+            _ => ParserType::Unknown,
+        }
+    }
+}
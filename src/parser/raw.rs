@@ -0,0 +1,14 @@
+use crate::parser::{MESSAGE_FIELD, _Parser, encode, error::Error};
+
+/// Stores the whole line as a message.
+pub(super) struct Raw;
+
+impl _Parser for Raw {
+    fn parse(&self, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+        let mut log = serde_json::Map::new();
+        // Raw text can be in any encoding, so we don't reject it.
+        let message = String::from_utf8_lossy(&data).into_owned();
+        log.insert(MESSAGE_FIELD.to_string(), serde_json::Value::String(message));
+        encode(&log)
+    }
+}
@@ -0,0 +1,189 @@
+use serde_json::{Map, Value};
+
+use crate::parser::{MESSAGE_FIELD, _Parser, encode, error::Error, to_str};
+
+// Time is stored in the default timestamp field, so RFC5424 logs get their event time.
+const TIME_FIELD: &str = "time";
+const NIL_VALUE: &str = "-";
+const MAX_PRIORITY: u8 = 191;
+// Severity names by their codes.
+const LEVELS: [&str; 8] = [
+    "emergency",
+    "alert",
+    "critical",
+    "error",
+    "warning",
+    "notice",
+    "info",
+    "debug",
+];
+// RFC3164 timestamp like "Oct 11 22:14:15".
+const RFC3164_TIME_LENGTH: usize = 15;
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Parses RFC5424 syslog messages and RFC3164 ones as much as possible,
+/// as the latter format is not strict.
+pub(super) struct Syslog;
+
+impl _Parser for Syslog {
+    fn parse(&self, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+        parse(&data)
+    }
+}
+
+pub(super) fn parse(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let line = to_str(data)?;
+    let (priority, line) = parse_priority(line)
+        .ok_or_else(|| Error::Parse("syslog priority is invalid".to_string()))?;
+    let mut log = Map::new();
+    log.insert("facility".to_string(), Value::from(priority / 8));
+    log.insert("severity".to_string(), Value::from(priority % 8));
+    log.insert("level".to_string(), Value::from(LEVELS[(priority % 8) as usize]));
+    // RFC5424 messages have version after priority, RFC3164 ones don't.
+    match line.strip_prefix("1 ") {
+        Some(line) => parse_rfc5424(line, &mut log)?,
+        None => parse_rfc3164(line, &mut log),
+    }
+    encode(&log)
+}
+
+/// Returns true if the line starts with syslog priority.
+pub(super) fn is_syslog(line: &[u8]) -> bool {
+    std::str::from_utf8(line).ok().and_then(parse_priority).is_some()
+}
+
+// Priority is "<number>" at the beginning of the line.
+fn parse_priority(line: &str) -> Option<(u8, &str)> {
+    let (priority, line) = line.strip_prefix('<')?.split_once('>')?;
+    if priority.is_empty() || priority.len() > 3 || !priority.bytes().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+    let priority: u8 = priority.parse().ok().filter(|x| *x <= MAX_PRIORITY)?;
+    Some((priority, line))
+}
+
+// <PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA [MSG]
+fn parse_rfc5424(line: &str, log: &mut Map<String, Value>) -> Result<(), Error> {
+    log.insert("version".to_string(), Value::from(1));
+    let mut rest = line;
+    for field in [TIME_FIELD, "hostname", "app_name", "proc_id", "msg_id"] {
+        let (value, next) = rest
+            .split_once(' ')
+            .ok_or_else(|| Error::Parse(format!("syslog {} is missing", field)))?;
+        if value != NIL_VALUE {
+            log.insert(field.to_string(), Value::from(value));
+        }
+        rest = next;
+    }
+    let (structured_data, rest) = parse_structured_data(rest)?;
+    if !structured_data.is_empty() {
+        log.insert("structured_data".to_string(), Value::Object(structured_data));
+    }
+    // Message can start with BOM if it is UTF-8.
+    let message = rest.strip_prefix(' ').unwrap_or(rest);
+    let message = message.strip_prefix('\u{feff}').unwrap_or(message);
+    if !message.is_empty() {
+        log.insert(MESSAGE_FIELD.to_string(), Value::from(message));
+    }
+    Ok(())
+}
+
+// [id name="value" ...][id ...] or "-", returns data by ids and the rest of the line.
+fn parse_structured_data(line: &str) -> Result<(Map<String, Value>, &str), Error> {
+    let mut data = Map::new();
+    if let Some(rest) = line.strip_prefix(NIL_VALUE) {
+        return Ok((data, rest));
+    }
+    let invalid = || Error::Parse("syslog structured data is invalid".to_string());
+    let mut rest = line;
+    while let Some(element) = rest.strip_prefix('[') {
+        let id_end = element.find([' ', ']']).ok_or_else(invalid)?;
+        let id = &element[..id_end];
+        let mut params = Map::new();
+        let mut chars = element[id_end..].char_indices();
+        loop {
+            match chars.next() {
+                Some((_, ' ')) => {
+                    let name: String =
+                        chars.by_ref().map(|x| x.1).take_while(|x| *x != '=').collect();
+                    if chars.next().map(|x| x.1) != Some('"') {
+                        return Err(invalid());
+                    }
+                    let mut value = String::new();
+                    loop {
+                        match chars.next().ok_or_else(invalid)?.1 {
+                            '"' => break,
+                            // Only ", \ and ] are escaped.
+                            '\\' => value.push(chars.next().ok_or_else(invalid)?.1),
+                            x => value.push(x),
+                        }
+                    }
+                    params.insert(name, Value::from(value));
+                }
+                Some((i, ']')) => {
+                    rest = &element[id_end + i + 1..];
+                    break;
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        data.insert(id.to_string(), Value::Object(params));
+    }
+    if data.is_empty() {
+        return Err(invalid());
+    }
+    Ok((data, rest))
+}
+
+// <PRI>TIMESTAMP HOSTNAME TAG[PID]: MSG, every part except message can be missing.
+fn parse_rfc3164(line: &str, log: &mut Map<String, Value>) {
+    let mut rest = line;
+    let time = rest.get(..RFC3164_TIME_LENGTH).filter(|x| is_rfc3164_time(x));
+    if let Some(time) = time {
+        log.insert(TIME_FIELD.to_string(), Value::from(time));
+        rest = rest[RFC3164_TIME_LENGTH..].trim_start_matches(' ');
+        // Hostname is followed by tag, but some senders skip hostname.
+        if let Some((hostname, next)) = rest.split_once(' ') {
+            if !hostname.is_empty() && parse_tag(hostname).is_none() {
+                log.insert("hostname".to_string(), Value::from(hostname));
+                rest = next;
+            }
+        }
+    }
+    if let Some((tag, pid, next)) = parse_tag(rest) {
+        log.insert("app_name".to_string(), Value::from(tag));
+        if let Some(pid) = pid {
+            log.insert("proc_id".to_string(), Value::from(pid));
+        }
+        rest = next;
+    }
+    log.insert(MESSAGE_FIELD.to_string(), Value::from(rest));
+}
+
+fn is_rfc3164_time(time: &str) -> bool {
+    let bytes = time.as_bytes();
+    MONTHS.iter().any(|x| bytes.starts_with(x.as_bytes()))
+        && bytes[3] == b' '
+        && bytes[6] == b' '
+        && bytes[9] == b':'
+        && bytes[12] == b':'
+}
+
+// Tag is "name:" or "name[pid]:" followed by a space.
+fn parse_tag(line: &str) -> Option<(&str, Option<&str>, &str)> {
+    let (tag, rest) = line.split_once(':')?;
+    let rest = rest.strip_prefix(' ').unwrap_or(rest);
+    let (tag, pid) = match tag.strip_suffix(']') {
+        Some(tag) => {
+            let (tag, pid) = tag.split_once('[')?;
+            (tag, Some(pid))
+        }
+        None => (tag, None),
+    };
+    if tag.is_empty() || !tag.bytes().all(|x| x.is_ascii_graphic() && x != b'[') {
+        return None;
+    }
+    Some((tag, pid, rest))
+}
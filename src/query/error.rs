@@ -0,0 +1,7 @@
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub(crate) enum Error {
+    #[error("invalid query syntax at position {position}: {message}")]
+    Syntax { position: usize, message: String },
+}
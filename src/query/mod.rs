@@ -0,0 +1,233 @@
+use std::ops::Bound;
+
+pub(crate) use parser::parse;
+
+pub(crate) mod error;
+mod parser;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Query {
+    // <field>:<value>
+    Term {
+        field: String,
+        value: String,
+    },
+    // <field>:[<from> TO <to>], <field>:{<from> TO <to>}, <field> > <value>, etc.
+    Range {
+        field: String,
+        from: Bound<String>,
+        to: Bound<String>,
+    },
+    // <field>:<value>* - matches string values which start with the value.
+    Prefix {
+        field: String,
+        value: String,
+    },
+    // <field>:<value with * and ?> - * matches any sequence of chars, ? matches any single char.
+    Wildcard {
+        field: String,
+        value: String,
+    },
+    // <field>:/<regex>/ - regex should match the whole string value.
+    Regex {
+        field: String,
+        value: String,
+    },
+    // Bare words without field.
+    Text(String),
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+}
+
+/// Converts wildcard pattern to regex which matches the same strings.
+pub(crate) fn wildcard_to_regex(pattern: &str) -> String {
+    let mut regex = String::new();
+    let mut literal = String::new();
+    for c in pattern.chars() {
+        let wildcard = match c {
+            '*' => ".*",
+            '?' => ".",
+            _ => {
+                literal.push(c);
+                continue;
+            }
+        };
+        regex.push_str(&regex::escape(&literal));
+        regex.push_str(wildcard);
+        literal.clear();
+    }
+    regex.push_str(&regex::escape(&literal));
+    regex
+}
+
+/// Returns the part of the wildcard pattern before the first wildcard.
+#[cfg(feature = "index_nonsense")]
+pub(crate) fn wildcard_prefix(pattern: &str) -> &str {
+    &pattern[..pattern.find(['*', '?']).unwrap_or(pattern.len())]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(field: &str, value: &str) -> Query {
+        Query::Term {
+            field: field.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    fn range(field: &str, from: Bound<&str>, to: Bound<&str>) -> Query {
+        Query::Range {
+            field: field.to_string(),
+            from: from.map(str::to_string),
+            to: to.map(str::to_string),
+        }
+    }
+
+    fn pattern(kind: fn(String, String) -> Query, field: &str, value: &str) -> Query {
+        kind(field.to_string(), value.to_string())
+    }
+
+    fn and(left: Query, right: Query) -> Query {
+        Query::And(Box::new(left), Box::new(right))
+    }
+
+    fn or(left: Query, right: Query) -> Query {
+        Query::Or(Box::new(left), Box::new(right))
+    }
+
+    fn not(query: Query) -> Query {
+        Query::Not(Box::new(query))
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("level:error").unwrap(), term("level", "error"));
+        assert_eq!(parse("  vars.id:1 ").unwrap(), term("vars.id", "1"));
+        assert_eq!(parse("time:10:00:00").unwrap(), term("time", "10:00:00"));
+        assert_eq!(
+            parse(r#"message:"hello \"big\" world""#).unwrap(),
+            term("message", r#"hello "big" world"#)
+        );
+        assert_eq!(parse("timeout").unwrap(), Query::Text("timeout".to_string()));
+        assert_eq!(
+            parse("level:error AND component:api").unwrap(),
+            and(term("level", "error"), term("component", "api"))
+        );
+        // AND is optional between clauses.
+        assert_eq!(
+            parse("level:error component:api").unwrap(),
+            and(term("level", "error"), term("component", "api"))
+        );
+        // AND has higher priority than OR.
+        assert_eq!(
+            parse("a:1 OR b:2 AND c:3").unwrap(),
+            or(term("a", "1"), and(term("b", "2"), term("c", "3")))
+        );
+        assert_eq!(
+            parse("(a:1 OR b:2) AND NOT c:3").unwrap(),
+            and(or(term("a", "1"), term("b", "2")), not(term("c", "3")))
+        );
+        assert_eq!(parse("NOT NOT a:1").unwrap(), not(not(term("a", "1"))));
+        assert_eq!(
+            parse("a:1 OR ORDER:2").unwrap(),
+            or(term("a", "1"), term("ORDER", "2")),
+            "keywords are detected only as separate words"
+        );
+    }
+
+    #[test]
+    fn test_parse_range() {
+        use Bound::*;
+
+        assert_eq!(parse("status>=500").unwrap(), range("status", Included("500"), Unbounded));
+        assert_eq!(
+            parse("vars.latency_ms > 250").unwrap(),
+            range("vars.latency_ms", Excluded("250"), Unbounded)
+        );
+        assert_eq!(parse("a<1.5").unwrap(), range("a", Unbounded, Excluded("1.5")));
+        assert_eq!(parse("a <= b").unwrap(), range("a", Unbounded, Included("b")));
+        assert_eq!(parse("a:[1 TO 5]").unwrap(), range("a", Included("1"), Included("5")));
+        assert_eq!(parse("a:{1 TO 5]").unwrap(), range("a", Excluded("1"), Included("5")));
+        assert_eq!(parse("a:[ * TO 5 }").unwrap(), range("a", Unbounded, Excluded("5")));
+        assert_eq!(
+            parse(r#"time:["2023-01-01 10:00" TO *]"#).unwrap(),
+            range("time", Included("2023-01-01 10:00"), Unbounded)
+        );
+        assert_eq!(
+            parse("status>=500 AND NOT a:[1 TO 5]").unwrap(),
+            and(
+                range("status", Included("500"), Unbounded),
+                not(range("a", Included("1"), Included("5")))
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_patterns() {
+        let prefix = |field, value| Query::Prefix { field, value };
+        let wildcard = |field, value| Query::Wildcard { field, value };
+        let regex = |field, value| Query::Regex { field, value };
+
+        assert_eq!(parse("message:timeout*").unwrap(), pattern(prefix, "message", "timeout"));
+        assert_eq!(parse("message:*").unwrap(), pattern(prefix, "message", ""));
+        assert_eq!(parse("message:*out").unwrap(), pattern(wildcard, "message", "*out"));
+        assert_eq!(parse("message:t?me*").unwrap(), pattern(wildcard, "message", "t?me*"));
+        assert_eq!(parse(r#"message:"timeout*""#).unwrap(), term("message", "timeout*"));
+        assert_eq!(parse(r"host:/web-\d+/").unwrap(), pattern(regex, "host", r"web-\d+"));
+        assert_eq!(parse(r"url:/\/api\/.*/").unwrap(), pattern(regex, "url", r"/api/.*"));
+        assert_eq!(
+            parse("host:/a b/ AND level:error").unwrap(),
+            and(pattern(regex, "host", "a b"), term("level", "error"))
+        );
+    }
+
+    #[test]
+    fn test_wildcard_to_regex() {
+        assert_eq!(wildcard_to_regex("a*b?c"), "a.*b.c");
+        assert_eq!(wildcard_to_regex("1.5*"), r"1\.5.*");
+    }
+
+    #[cfg(feature = "index_nonsense")]
+    #[test]
+    fn test_wildcard_prefix() {
+        assert_eq!(wildcard_prefix("web-*-?"), "web-");
+        assert_eq!(wildcard_prefix("web"), "web");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let cases = [
+            ("", 0, "query is empty"),
+            ("   ", 3, "query is empty"),
+            ("level:", 6, "expected value"),
+            ("level: error", 6, "expected value"),
+            ("(level:error", 12, "expected ')'"),
+            ("level:error)", 11, "unexpected ')'"),
+            ("level:error AND", 15, "unexpected end of query"),
+            ("level:error OR OR a:1", 15, "unexpected keyword OR"),
+            (r#"message:"hello"#, 8, "unterminated quoted string"),
+            ("()", 1, "unexpected ')'"),
+            ("status>", 7, "expected value"),
+            ("status>=)", 8, "expected value"),
+            ("a:[1 5]", 5, "expected TO"),
+            ("a:[1 TO 5", 9, "expected ']' or '}'"),
+            ("a:[TO 5]", 3, "expected value"),
+            ("host:/web", 5, "unterminated regex"),
+            ("host:/web(/", 5, "invalid regex: unclosed group"),
+        ];
+        for (query, position, message) in cases {
+            assert_eq!(
+                parse(query).unwrap_err(),
+                error::Error::Syntax {
+                    position,
+                    message: message.to_string()
+                },
+                "query: {}",
+                query
+            );
+        }
+    }
+}
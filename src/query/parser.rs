@@ -0,0 +1,342 @@
+use std::ops::Bound;
+
+use super::error::Error;
+use super::Query;
+
+const KEYWORD_AND: &str = "AND";
+const KEYWORD_OR: &str = "OR";
+const KEYWORD_NOT: &str = "NOT";
+const KEYWORD_TO: &str = "TO";
+const UNBOUNDED: &str = "*";
+
+/// Parses query into AST.
+///
+/// Grammar (NOT has the highest priority, OR has the lowest):
+///   or     = and ("OR" and)*
+///   and    = not (["AND"] not)*
+///   not    = "NOT" not | primary
+///   primary = "(" or ")" | <field>:<value> | <field>:<range> | <field> <op> <value>
+///             | <field>:/<regex>/ | <word> | "<words>"
+///   range  = ("[" | "{") (<value> | "*") "TO" (<value> | "*") ("]" | "}")
+///   op     = "<" | "<=" | ">" | ">="
+pub(crate) fn parse(input: &str) -> Result<Query, Error> {
+    let mut parser = Parser { input, position: 0 };
+    parser.skip_whitespaces();
+    if parser.is_end() {
+        return Err(parser.error("query is empty"));
+    }
+    let query = parser.parse_or()?;
+    parser.skip_whitespaces();
+    // Parsing can stop before the end of the query only on closing bracket without opening one.
+    if !parser.is_end() {
+        return Err(parser.error("unexpected ')'"));
+    }
+    Ok(query)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    // Position in bytes.
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn parse_or(&mut self) -> Result<Query, Error> {
+        let mut left = self.parse_and()?;
+        while self.consume_keyword(KEYWORD_OR) {
+            let right = self.parse_and()?;
+            left = Query::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Query, Error> {
+        let mut left = self.parse_not()?;
+        loop {
+            if !self.consume_keyword(KEYWORD_AND) {
+                // Clauses without operator between them are joined by AND.
+                self.skip_whitespaces();
+                if self.is_end() || self.peek() == Some(')') || self.is_keyword(KEYWORD_OR) {
+                    return Ok(left);
+                }
+            }
+            let right = self.parse_not()?;
+            left = Query::And(Box::new(left), Box::new(right));
+        }
+    }
+
+    fn parse_not(&mut self) -> Result<Query, Error> {
+        if self.consume_keyword(KEYWORD_NOT) {
+            return Ok(Query::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Query, Error> {
+        self.skip_whitespaces();
+        match self.peek() {
+            None => Err(self.error("unexpected end of query")),
+            Some('(') => {
+                self.position += 1;
+                let query = self.parse_or()?;
+                self.skip_whitespaces();
+                if self.peek() != Some(')') {
+                    return Err(self.error("expected ')'"));
+                }
+                self.position += 1;
+                Ok(query)
+            }
+            Some(')') => Err(self.error("unexpected ')'")),
+            Some('"') => Ok(Query::Text(self.parse_quoted()?)),
+            Some(_) => self.parse_clause(),
+        }
+    }
+
+    fn parse_clause(&mut self) -> Result<Query, Error> {
+        let start = self.position;
+        let word = self.take_while(is_word_char);
+        if [KEYWORD_AND, KEYWORD_OR, KEYWORD_NOT].contains(&word) {
+            self.position = start;
+            return Err(self.error(&format!("unexpected keyword {}", word)));
+        }
+        if let Some(query) = self.parse_comparison(word)? {
+            return Ok(query);
+        }
+        if self.peek() != Some(':') {
+            return Ok(Query::Text(word.to_string()));
+        }
+        self.position += 1;
+        if let Some(query) = self.parse_range(word)? {
+            return Ok(query);
+        }
+        if self.peek() == Some('/') {
+            return self.parse_regex(word);
+        }
+        let quoted = self.peek() == Some('"');
+        let value = self.parse_value()?;
+        let field = word.to_string();
+        // Wildcards in quoted values are matched literally.
+        if quoted || !value.contains(['*', '?']) {
+            return Ok(Query::Term { field, value });
+        }
+        match value.strip_suffix('*') {
+            Some(prefix) if !prefix.contains(['*', '?']) => Ok(Query::Prefix {
+                field,
+                value: prefix.to_string(),
+            }),
+            _ => Ok(Query::Wildcard { field, value }),
+        }
+    }
+
+    fn parse_regex(&mut self, field: &str) -> Result<Query, Error> {
+        let start = self.position;
+        // Skip opening slash.
+        self.position += 1;
+        let mut value = String::new();
+        let mut escaped = false;
+        for (i, c) in self.input[self.position..].char_indices() {
+            match c {
+                // Slash is escaped only to not close the regex.
+                '/' if escaped => {
+                    value.push(c);
+                    escaped = false;
+                }
+                _ if escaped => {
+                    value.push('\\');
+                    value.push(c);
+                    escaped = false;
+                }
+                '\\' => escaped = true,
+                '/' => {
+                    self.position += i + 1;
+                    if let Err(e) = regex::Regex::new(&value) {
+                        self.position = start;
+                        return Err(self.error(&format!("invalid regex: {}", regex_error(e))));
+                    }
+                    return Ok(Query::Regex {
+                        field: field.to_string(),
+                        value,
+                    });
+                }
+                _ => value.push(c),
+            }
+        }
+        self.position = start;
+        Err(self.error("unterminated regex"))
+    }
+
+    fn parse_comparison(&mut self, field: &str) -> Result<Option<Query>, Error> {
+        let start = self.position;
+        self.skip_whitespaces();
+        let Some(operator) =
+            ["<=", ">=", "<", ">"].into_iter().find(|x| self.input[self.position..].starts_with(x))
+        else {
+            // It is not a comparison, so we return whitespaces back.
+            self.position = start;
+            return Ok(None);
+        };
+        self.position += operator.len();
+        self.skip_whitespaces();
+        let value = self.parse_value()?;
+        let (from, to) = match operator {
+            "<=" => (Bound::Unbounded, Bound::Included(value)),
+            ">=" => (Bound::Included(value), Bound::Unbounded),
+            "<" => (Bound::Unbounded, Bound::Excluded(value)),
+            _ => (Bound::Excluded(value), Bound::Unbounded),
+        };
+        Ok(Some(Query::Range {
+            field: field.to_string(),
+            from,
+            to,
+        }))
+    }
+
+    fn parse_range(&mut self, field: &str) -> Result<Option<Query>, Error> {
+        let inclusive_from = match self.peek() {
+            Some('[') => true,
+            Some('{') => false,
+            _ => return Ok(None),
+        };
+        self.position += 1;
+        self.skip_whitespaces();
+        let from = self.parse_range_value()?;
+        if !self.consume_keyword(KEYWORD_TO) {
+            return Err(self.error("expected TO"));
+        }
+        self.skip_whitespaces();
+        let to = self.parse_range_value()?;
+        self.skip_whitespaces();
+        let inclusive_to = match self.peek() {
+            Some(']') => true,
+            Some('}') => false,
+            _ => return Err(self.error("expected ']' or '}'")),
+        };
+        self.position += 1;
+        Ok(Some(Query::Range {
+            field: field.to_string(),
+            from: to_bound(from, inclusive_from),
+            to: to_bound(to, inclusive_to),
+        }))
+    }
+
+    // Returns None for unbounded value.
+    fn parse_range_value(&mut self) -> Result<Option<String>, Error> {
+        if self.peek() == Some('"') {
+            return self.parse_quoted().map(Some);
+        }
+        let start = self.position;
+        let value = self.take_while(is_range_value_char);
+        if value.is_empty() || value == KEYWORD_TO {
+            self.position = start;
+            return Err(self.error("expected value"));
+        }
+        if value == UNBOUNDED {
+            return Ok(None);
+        }
+        Ok(Some(value.to_string()))
+    }
+
+    fn parse_value(&mut self) -> Result<String, Error> {
+        if self.peek() == Some('"') {
+            return self.parse_quoted();
+        }
+        let value = self.take_while(is_value_char);
+        if value.is_empty() {
+            return Err(self.error("expected value"));
+        }
+        Ok(value.to_string())
+    }
+
+    fn parse_quoted(&mut self) -> Result<String, Error> {
+        let start = self.position;
+        // Skip opening quote.
+        self.position += 1;
+        let mut value = String::new();
+        let mut escaped = false;
+        for (i, c) in self.input[self.position..].char_indices() {
+            match c {
+                _ if escaped => {
+                    value.push(c);
+                    escaped = false;
+                }
+                '\\' => escaped = true,
+                '"' => {
+                    self.position += i + 1;
+                    return Ok(value);
+                }
+                _ => value.push(c),
+            }
+        }
+        self.position = start;
+        Err(self.error("unterminated quoted string"))
+    }
+
+    fn take_while(&mut self, f: fn(char) -> bool) -> &'a str {
+        let rest = &self.input[self.position..];
+        let length = rest.find(|c| !f(c)).unwrap_or(rest.len());
+        self.position += length;
+        &rest[..length]
+    }
+
+    fn is_keyword(&mut self, keyword: &str) -> bool {
+        self.skip_whitespaces();
+        let rest = &self.input[self.position..];
+        rest.starts_with(keyword)
+            && rest[keyword.len()..].chars().next().is_none_or(|c| c.is_whitespace() || c == '(')
+    }
+
+    fn consume_keyword(&mut self, keyword: &str) -> bool {
+        if !self.is_keyword(keyword) {
+            return false;
+        }
+        self.position += keyword.len();
+        true
+    }
+
+    fn skip_whitespaces(&mut self) {
+        self.take_while(char::is_whitespace);
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.position..].chars().next()
+    }
+
+    fn is_end(&self) -> bool {
+        self.position == self.input.len()
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error::Syntax {
+            // We return position in chars as it is what user sees.
+            position: self.input[..self.position].chars().count(),
+            message: message.to_string(),
+        }
+    }
+}
+
+// Regex syntax errors contain pattern with pointer to the error,
+// but we need only the error itself as we already return error position.
+fn regex_error(e: regex::Error) -> String {
+    let e = e.to_string();
+    e.lines().last().and_then(|x| x.strip_prefix("error: ")).unwrap_or(&e).to_string()
+}
+
+fn to_bound(value: Option<String>, inclusive: bool) -> Bound<String> {
+    match value {
+        None => Bound::Unbounded,
+        Some(value) if inclusive => Bound::Included(value),
+        Some(value) => Bound::Excluded(value),
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    !c.is_whitespace() && !matches!(c, '(' | ')' | ':' | '"' | '<' | '>')
+}
+
+fn is_value_char(c: char) -> bool {
+    !c.is_whitespace() && !matches!(c, '(' | ')')
+}
+
+fn is_range_value_char(c: char) -> bool {
+    !c.is_whitespace() && !matches!(c, ']' | '}')
+}
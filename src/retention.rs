@@ -0,0 +1,119 @@
+use tokio::sync::watch;
+use tracing::{debug, info};
+
+use crate::{
+    config::Config,
+    key::key_time,
+    log_storage::{Key, LogStoragePointer},
+    storage::Stats,
+};
+
+// Eviction is blocking, so it is split into batches to let other tasks
+// run on the same runtime thread while a lot of logs are evicted.
+const EVICTION_BATCH_SIZE: usize = 1000;
+
+const NANOS_IN_SECOND: u64 = 1_000_000_000;
+
+/// Limits of stored logs, the oldest logs are evicted when any of them is exceeded.
+/// Zero limit is disabled.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Retention {
+    max_age: u64, // nanoseconds
+    max_bytes: u64,
+    max_records: u64,
+    interval: tokio::time::Duration,
+}
+
+impl Retention {
+    pub(crate) fn new(cfg: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        if cfg.retention_interval == 0 {
+            return Err("retention interval should be greater than zero".into());
+        }
+        Ok(Self {
+            max_age: cfg.retention_max_age.saturating_mul(NANOS_IN_SECOND),
+            max_bytes: cfg.retention_max_bytes,
+            max_records: cfg.retention_max_records,
+            interval: tokio::time::Duration::from_secs(cfg.retention_interval),
+        })
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.max_age > 0 || self.max_bytes > 0 || self.max_records > 0
+    }
+
+    /// Returns true if the oldest log should be evicted.
+    /// Age is checked by the time when log was received as it is encoded in its key.
+    pub(crate) fn is_exceeded(&self, oldest: Key, stats: &Stats, now: u64) -> bool {
+        (self.max_records > 0 && stats.records > self.max_records)
+            || (self.max_bytes > 0 && stats.bytes > self.max_bytes)
+            || (self.max_age > 0 && key_time(oldest).saturating_add(self.max_age) < now)
+    }
+
+    pub(crate) async fn start(
+        &self,
+        log_storage: LogStoragePointer,
+        mut shutdown_rx: watch::Receiver<()>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.is_enabled() {
+            debug!("retention is disabled");
+            return Ok(());
+        }
+        let mut interval = tokio::time::interval(self.interval);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => self.enforce(&log_storage).await?,
+                _ = shutdown_rx.changed() => {
+                    debug!("received shutdown signal; stop retention routine");
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    async fn enforce(
+        &self,
+        log_storage: &LogStoragePointer,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut evicted = 0;
+        loop {
+            let n = log_storage.evict(self, EVICTION_BATCH_SIZE)?;
+            evicted += n;
+            if n < EVICTION_BATCH_SIZE {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        if evicted > 0 {
+            info!(evicted, "old logs are evicted by retention policy");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{key::min_key, retention::*};
+
+    #[test]
+    fn test_is_exceeded() {
+        let retention = |max_age: u64, max_bytes: u64, max_records: u64| Retention {
+            max_age,
+            max_bytes,
+            max_records,
+            interval: tokio::time::Duration::from_secs(1),
+        };
+        let stats = Stats {
+            records: 10,
+            bytes: 100,
+            ..Stats::default()
+        };
+        let now = 1_700_000_000 * NANOS_IN_SECOND;
+        let oldest = min_key(now - 5 * NANOS_IN_SECOND);
+        assert!(!retention(0, 0, 0).is_exceeded(oldest, &stats, now));
+        assert!(!retention(0, 100, 10).is_exceeded(oldest, &stats, now));
+        assert!(retention(0, 99, 0).is_exceeded(oldest, &stats, now));
+        assert!(retention(0, 0, 9).is_exceeded(oldest, &stats, now));
+        assert!(!retention(5 * NANOS_IN_SECOND, 0, 0).is_exceeded(oldest, &stats, now));
+        assert!(retention(4 * NANOS_IN_SECOND, 0, 0).is_exceeded(oldest, &stats, now));
+    }
+}
@@ -0,0 +1,541 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+
+use serde::Serialize;
+
+use crate::{
+    index::{
+        aggregation::{Aggregation, AggregationResult, Histogram, HistogramBucket, ValueCounts},
+        analyzer::Analyzer,
+        matcher::Matcher,
+        FindOptions, FlattenLimits, Hit, Order,
+    },
+    log_storage::{Key, SearchResult, Stats},
+    parser::{self, Parser},
+    query::{self, Query},
+    shared,
+};
+
+use super::http::{Request, Response, MAX_BODY_SIZE};
+
+const DEFAULT_SEARCH_LIMIT: usize = 100;
+const MAX_SEARCH_LIMIT: usize = 1000;
+const DEFAULT_TERMS_SIZE: usize = 10;
+const MAX_TERMS_SIZE: usize = 1000;
+const DEFAULT_PERCENTS: &str = "50,95,99";
+const DEFAULT_HISTOGRAM_INTERVAL: &str = "1m";
+// Number of buckets before the end of the range if the start is not set.
+const DEFAULT_HISTOGRAM_BUCKETS: u64 = 60;
+const MAX_HISTOGRAM_BUCKETS: u64 = 10_000;
+
+const CONTENT_TYPE_JSON: &str = "application/json";
+
+const JSON_FORMAT: &str = "json";
+const AUTO_FORMAT: &str = "auto";
+
+pub(super) struct SearchParams {
+    pub(super) query: Query,
+    pub(super) options: FindOptions,
+}
+
+impl SearchParams {
+    pub(super) fn parse(request: &Request) -> Result<Self, String> {
+        let query = request.param("q").ok_or("q parameter is required")?;
+        let query = query::parse(query).map_err(|e| e.to_string())?;
+        // Since is an older name of from parameter.
+        let from = match parse_param(request, "from")? {
+            Some(from) => Some(from),
+            None => parse_param(request, "since")?,
+        };
+        let to = parse_param(request, "to")?;
+        let limit = parse_param(request, "limit")?.unwrap_or(DEFAULT_SEARCH_LIMIT);
+        if limit == 0 || limit > MAX_SEARCH_LIMIT {
+            return Err(format!("limit should be from 1 to {}", MAX_SEARCH_LIMIT));
+        }
+        let order = parse_param(request, "order")?.unwrap_or(Order::Desc);
+        let cursor = parse_param(request, "cursor")?;
+        Ok(Self {
+            query,
+            options: FindOptions {
+                from,
+                to,
+                order,
+                limit,
+                cursor,
+            },
+        })
+    }
+}
+
+pub(super) struct AggregateParams {
+    pub(super) query: Query,
+    pub(super) options: FindOptions,
+    pub(super) aggregation: Aggregation,
+}
+
+impl AggregateParams {
+    pub(super) fn parse(request: &Request) -> Result<Self, String> {
+        let query = request.param("q").ok_or("q parameter is required")?;
+        let query = query::parse(query).map_err(|e| e.to_string())?;
+        let options = FindOptions {
+            from: parse_param(request, "from")?,
+            to: parse_param(request, "to")?,
+            ..FindOptions::default()
+        };
+        let field = || match request.param("field") {
+            Some(field) if !field.is_empty() => Ok(field.to_string()),
+            _ => Err("field parameter is required".to_string()),
+        };
+        let aggregation = match request.param("type").unwrap_or("count") {
+            "count" => Aggregation::Count,
+            "terms" => {
+                let size = parse_param(request, "size")?.unwrap_or(DEFAULT_TERMS_SIZE);
+                if size == 0 || size > MAX_TERMS_SIZE {
+                    return Err(format!("size should be from 1 to {}", MAX_TERMS_SIZE));
+                }
+                Aggregation::Terms {
+                    field: field()?,
+                    size,
+                }
+            }
+            "stats" => Aggregation::Stats { field: field()? },
+            "percentiles" => {
+                let mut percents: Vec<f64> = Vec::new();
+                for percent in request.param("percents").unwrap_or(DEFAULT_PERCENTS).split(',') {
+                    match percent.trim().parse::<f64>() {
+                        Ok(percent) if (0.0..=100.0).contains(&percent) => percents.push(percent),
+                        _ => return Err(format!("invalid percent: {}", percent)),
+                    }
+                }
+                Aggregation::Percentiles {
+                    field: field()?,
+                    percents,
+                }
+            }
+            kind => return Err(format!("unknown aggregation type: {}", kind)),
+        };
+        Ok(Self {
+            query,
+            options,
+            aggregation,
+        })
+    }
+}
+
+pub(super) struct HistogramParams {
+    pub(super) query: Query,
+    pub(super) histogram: Histogram,
+}
+
+impl HistogramParams {
+    pub(super) fn parse(request: &Request) -> Result<Self, String> {
+        let query = request.param("q").ok_or("q parameter is required")?;
+        let query = query::parse(query).map_err(|e| e.to_string())?;
+        let interval =
+            parse_interval(request.param("interval").unwrap_or(DEFAULT_HISTOGRAM_INTERVAL))?;
+        let to = match parse_param(request, "to")? {
+            Some(to) => to,
+            None => shared::now_as_nanos_u64().map_err(|e| e.to_string())?,
+        };
+        let from = parse_param(request, "from")?.unwrap_or_else(|| {
+            to.saturating_sub(interval.saturating_mul(DEFAULT_HISTOGRAM_BUCKETS))
+        });
+        if from >= to {
+            return Err("from should be before to".to_string());
+        }
+        if (to - (from - from % interval)).div_ceil(interval) > MAX_HISTOGRAM_BUCKETS {
+            return Err(format!(
+                "histogram can't have more than {} buckets, increase interval",
+                MAX_HISTOGRAM_BUCKETS
+            ));
+        }
+        let split = request.param("split").filter(|x| !x.is_empty()).map(str::to_string);
+        Ok(Self {
+            query,
+            histogram: Histogram {
+                from,
+                to,
+                interval,
+                split,
+            },
+        })
+    }
+}
+
+pub(super) struct DeleteParams {
+    pub(super) query: Query,
+    pub(super) dry_run: bool,
+}
+
+impl DeleteParams {
+    pub(super) fn parse(request: &Request) -> Result<Self, String> {
+        let query = request.param("q").ok_or("q parameter is required")?;
+        let query = query::parse(query).map_err(|e| e.to_string())?;
+        // Parameter without value enables dry run too.
+        let dry_run = match request.param("dry_run") {
+            Some("") => true,
+            _ => parse_param(request, "dry_run")?.unwrap_or_default(),
+        };
+        Ok(Self { query, dry_run })
+    }
+}
+
+/// Logs from the ingest request body, which is newline delimited logs
+/// or JSON array of logs.
+pub(super) struct IngestBody {
+    pub(super) records: Vec<Vec<u8>>,
+    pub(super) rejected: Vec<IngestError>,
+}
+
+impl IngestBody {
+    /// Format parameter overrides the given parser of the listener.
+    pub(super) fn parse(request: &Request, parser: &Parser) -> Result<Self, String> {
+        let format_parser;
+        let parser = match request.param("format") {
+            Some(format) => {
+                format_parser = parser::new_parser(format).map_err(|e| e.to_string())?;
+                &format_parser
+            }
+            None => parser,
+        };
+        let body = match request.header("content-encoding") {
+            None | Some("identity") => request.body.clone(),
+            Some("gzip") => decode_gzip(&request.body)?,
+            Some(encoding) => return Err(format!("unsupported content encoding: {}", encoding)),
+        };
+        let mut records: Vec<Vec<u8>> = Vec::new();
+        let mut rejected: Vec<IngestError> = Vec::new();
+        let mut check = |line: usize, data: Vec<u8>, parser: &Parser| match parser.parse(data) {
+            Ok(data) => records.push(data),
+            Err(e) => rejected.push(IngestError {
+                line,
+                reason: e.to_string(),
+            }),
+        };
+        match parse_json_array(&body, request.param("format"))? {
+            // Array elements are numbered as lines and they are always JSON logs.
+            Some(logs) => {
+                let json = parser::new_parser(JSON_FORMAT).map_err(|e| e.to_string())?;
+                for (i, log) in logs.into_iter().enumerate() {
+                    check(i + 1, log.get().as_bytes().to_vec(), &json);
+                }
+            }
+            None => {
+                for (i, line) in body.split(|x| *x == b'\n').enumerate() {
+                    let line = line.strip_suffix(b"\r").unwrap_or(line);
+                    // Empty lines are skipped as in TCP ingestion.
+                    if !line.is_empty() {
+                        check(i + 1, line.to_vec(), parser);
+                    }
+                }
+            }
+        }
+        Ok(Self { records, rejected })
+    }
+}
+
+pub(super) struct EventsParams {
+    pub(super) query: Query,
+    pub(super) matcher: Matcher,
+    pub(super) last_event_id: Option<Key>,
+}
+
+impl EventsParams {
+    pub(super) fn parse(
+        request: &Request,
+        limits: FlattenLimits,
+        analyzer: &Analyzer,
+    ) -> Result<Self, String> {
+        let query = request.param("q").ok_or("q parameter is required")?;
+        let query = query::parse(query).map_err(|e| e.to_string())?;
+        let matcher = Matcher::new(&query, limits, analyzer).map_err(|e| e.to_string())?;
+        // Browsers send id of the last received event on reconnect.
+        let last_event_id = match request.header("last-event-id") {
+            Some(id) => Some(id.parse().map_err(|_| format!("invalid last event id: {}", id))?),
+            None => None,
+        };
+        Ok(Self {
+            query,
+            matcher,
+            last_event_id,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub(super) struct SearchResponse {
+    hits: Vec<SearchHit>,
+    total: usize,
+    cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SearchHit {
+    key: Key,
+    timestamp: u64,
+    log: serde_json::Value,
+}
+
+impl From<SearchResult> for SearchResponse {
+    fn from(result: SearchResult) -> Self {
+        let hits = result
+            .hits
+            .into_iter()
+            .map(|(Hit { timestamp, key }, data)| SearchHit {
+                key,
+                timestamp,
+                // Indexed logs are always valid JSON, but we don't want to fail the whole search.
+                log: serde_json::from_slice(&data).unwrap_or_else(|_| {
+                    serde_json::Value::String(String::from_utf8_lossy(&data).to_string())
+                }),
+            })
+            .collect();
+        Self {
+            hits,
+            total: result.total,
+            cursor: result.cursor.map(|x| x.to_string()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub(super) enum AggregateResponse {
+    Count {
+        count: usize,
+    },
+    Terms {
+        count: usize,
+        buckets: Vec<AggregateBucket>,
+        other: usize,
+    },
+    // Values are null if there are no numeric values.
+    Stats {
+        count: usize,
+        values: usize,
+        min: Option<serde_json::Value>,
+        max: Option<serde_json::Value>,
+        sum: Option<f64>,
+        avg: Option<f64>,
+    },
+    Percentiles {
+        count: usize,
+        percentiles: Vec<AggregatePercentile>,
+    },
+}
+
+#[derive(Serialize)]
+pub(super) struct AggregateBucket {
+    value: serde_json::Value,
+    count: usize,
+}
+
+#[derive(Serialize)]
+pub(super) struct AggregatePercentile {
+    percent: f64,
+    value: Option<serde_json::Value>,
+}
+
+impl From<AggregationResult> for AggregateResponse {
+    fn from(result: AggregationResult) -> Self {
+        match result {
+            AggregationResult::Count(count) => Self::Count { count },
+            AggregationResult::Terms {
+                count,
+                buckets,
+                other,
+            } => Self::Terms {
+                count,
+                buckets: buckets
+                    .into_iter()
+                    .map(|(value, count)| AggregateBucket {
+                        value: value.to_json(),
+                        count,
+                    })
+                    .collect(),
+                other,
+            },
+            AggregationResult::Stats { count, stats } => Self::Stats {
+                count,
+                values: stats.as_ref().map_or(0, |x| x.values),
+                min: stats.as_ref().map(|x| x.min.to_json()),
+                max: stats.as_ref().map(|x| x.max.to_json()),
+                sum: stats.as_ref().map(|x| x.sum),
+                avg: stats.as_ref().map(|x| x.avg),
+            },
+            AggregationResult::Percentiles { count, percentiles } => Self::Percentiles {
+                count,
+                percentiles: percentiles
+                    .into_iter()
+                    .map(|(percent, value)| AggregatePercentile {
+                        percent,
+                        value: value.map(|x| x.to_json()),
+                    })
+                    .collect(),
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(super) struct HistogramResponse {
+    // Bucket size in nanoseconds.
+    interval: u64,
+    buckets: Vec<HistogramResponseBucket>,
+}
+
+#[derive(Serialize)]
+struct HistogramResponseBucket {
+    start: u64,
+    count: usize,
+    // Number of logs by values of the split field, logs without the field are not counted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    split: Option<BTreeMap<String, usize>>,
+}
+
+impl HistogramResponse {
+    pub(super) fn new(histogram: &Histogram, buckets: Vec<HistogramBucket>) -> Self {
+        let buckets = buckets
+            .into_iter()
+            .map(|bucket| HistogramResponseBucket {
+                start: bucket.start,
+                count: bucket.count,
+                split: histogram.split.as_ref().map(|_| split_counts(bucket.split)),
+            })
+            .collect();
+        Self {
+            interval: histogram.interval,
+            buckets,
+        }
+    }
+}
+
+// Values of different types with the same string, like 1 and "1", are counted together.
+fn split_counts(counts: ValueCounts) -> BTreeMap<String, usize> {
+    let mut split: BTreeMap<String, usize> = BTreeMap::new();
+    for (value, count) in counts {
+        *split.entry(value.to_string()).or_default() += count;
+    }
+    split
+}
+
+#[derive(Serialize)]
+pub(super) struct DeleteResponse {
+    pub(super) deleted: usize,
+    pub(super) dry_run: bool,
+}
+
+#[derive(Serialize)]
+pub(super) struct IngestResponse {
+    pub(super) accepted: usize,
+    pub(super) rejected: usize,
+    pub(super) errors: Vec<IngestError>,
+}
+
+#[derive(Serialize)]
+pub(super) struct IngestError {
+    // Line number in the body starting from 1.
+    pub(super) line: usize,
+    reason: String,
+}
+
+#[derive(Serialize)]
+pub(super) struct StatsResponse {
+    records: u64,
+    bytes: u64,
+    disk_bytes: u64,
+    compression_ratio: f64,
+    evicted_records: u64,
+    evicted_bytes: u64,
+}
+
+impl From<Stats> for StatsResponse {
+    fn from(stats: Stats) -> Self {
+        Self {
+            records: stats.records,
+            bytes: stats.bytes,
+            disk_bytes: stats.disk_bytes,
+            compression_ratio: stats.compression_ratio,
+            evicted_records: stats.evicted_records,
+            evicted_bytes: stats.evicted_bytes,
+        }
+    }
+}
+
+pub(super) fn json<T: Serialize>(status: u16, value: &T) -> Response {
+    match serde_json::to_vec(value) {
+        Ok(body) => Response::new(status).with_body(CONTENT_TYPE_JSON, body),
+        Err(e) => error(500, &e.to_string()),
+    }
+}
+
+pub(super) fn error(status: u16, message: &str) -> Response {
+    let body = serde_json::json!({ "error": message }).to_string().into_bytes();
+    Response::new(status).with_body(CONTENT_TYPE_JSON, body)
+}
+
+// Body is a JSON array only if it is parsed as JSON, as raw lines can start with [ too.
+// Invalid array is an error only if JSON format is requested explicitly.
+fn parse_json_array<'a>(
+    body: &'a [u8],
+    format: Option<&str>,
+) -> Result<Option<Vec<&'a serde_json::value::RawValue>>, String> {
+    if !matches!(format, None | Some(JSON_FORMAT) | Some(AUTO_FORMAT))
+        || !body.trim_ascii_start().starts_with(b"[")
+    {
+        return Ok(None);
+    }
+    match serde_json::from_slice(body) {
+        Ok(logs) => Ok(Some(logs)),
+        Err(e) if format == Some(JSON_FORMAT) => Err(format!("invalid json array: {}", e)),
+        Err(_) => Ok(None),
+    }
+}
+
+// Decompressed body is limited as well as the plain one.
+fn decode_gzip(body: &[u8]) -> Result<Vec<u8>, String> {
+    let mut data: Vec<u8> = Vec::new();
+    flate2::read::GzDecoder::new(body)
+        .take(MAX_BODY_SIZE as u64 + 1)
+        .read_to_end(&mut data)
+        .map_err(|e| format!("invalid gzip body: {}", e))?;
+    if data.len() > MAX_BODY_SIZE {
+        return Err(format!("decompressed body is larger than {} bytes", MAX_BODY_SIZE));
+    }
+    Ok(data)
+}
+
+// Interval is a number with ms, s, m, h or d unit, it is returned in nanoseconds.
+fn parse_interval(value: &str) -> Result<u64, String> {
+    let err = || format!("invalid interval: {}", value);
+    let unit_start = value.find(|c: char| !c.is_ascii_digit()).ok_or_else(err)?;
+    let (number, unit) = value.split_at(unit_start);
+    let number: u64 = number.parse().map_err(|_| err())?;
+    let multiplier: u64 = match unit {
+        "ms" => 1_000_000,
+        "s" => 1_000_000_000,
+        "m" => 60 * 1_000_000_000,
+        "h" => 60 * 60 * 1_000_000_000,
+        "d" => 24 * 60 * 60 * 1_000_000_000,
+        _ => return Err(err()),
+    };
+    match number.checked_mul(multiplier) {
+        Some(0) | None => Err(err()),
+        Some(interval) => Ok(interval),
+    }
+}
+
+// Returns None if there is no such parameter.
+fn parse_param<T>(request: &Request, name: &str) -> Result<Option<T>, String>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    match request.param(name) {
+        Some(value) => {
+            let value = value.parse().map_err(|e| format!("invalid {} parameter: {}", name, e))?;
+            Ok(Some(value))
+        }
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,47 @@
+use tokio::time::{Duration, Instant};
+
+// Limits of the batch, it is stored when any of them is reached.
+const BATCH_MAX_RECORDS: usize = 1000;
+const BATCH_MAX_BYTES: usize = 1024 * 1024;
+const BATCH_MAX_LATENCY: Duration = Duration::from_millis(50);
+
+/// Collects received logs to store them together.
+pub(super) struct Batcher {
+    records: Vec<Vec<u8>>,
+    bytes: usize,
+    // Time when the first log was added to the batch.
+    started: Option<Instant>,
+}
+
+impl Batcher {
+    pub(super) fn new() -> Self {
+        Self {
+            records: Vec::new(),
+            bytes: 0,
+            started: None,
+        }
+    }
+
+    pub(super) fn push(&mut self, data: Vec<u8>) {
+        self.started.get_or_insert_with(Instant::now);
+        self.bytes += data.len();
+        self.records.push(data);
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub(super) fn is_full(&self) -> bool {
+        self.records.len() >= BATCH_MAX_RECORDS
+            || self.bytes >= BATCH_MAX_BYTES
+            || self.started.is_some_and(|x| x.elapsed() >= BATCH_MAX_LATENCY)
+    }
+
+    /// Returns collected logs and starts a new batch.
+    pub(super) fn take(&mut self) -> Vec<Vec<u8>> {
+        self.bytes = 0;
+        self.started = None;
+        std::mem::take(&mut self.records)
+    }
+}
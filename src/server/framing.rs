@@ -0,0 +1,242 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const UNKNOWN: &str = "unknown";
+const NEWLINE: &str = "newline";
+const LENGTH_PREFIXED: &str = "length_prefixed";
+
+// Length prefix is big endian u32.
+const LENGTH_PREFIX_SIZE: usize = 4;
+const READ_BUFFER_SIZE: usize = 8 * 1024;
+// Telnet client sends it on ctrl+c.
+const TELNET_INTERRUPT: [u8; 5] = [255, 244, 255, 253, 6];
+
+#[derive(Clone, Copy)]
+pub(super) enum Framing {
+    Unknown,
+    // Every record ends with \n.
+    Newline,
+    // Every record starts with its length as big endian u32.
+    LengthPrefixed,
+}
+
+impl Display for Framing {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            Framing::Unknown => UNKNOWN.to_string(),
+            Framing::Newline => NEWLINE.to_string(),
+            Framing::LengthPrefixed => LENGTH_PREFIXED.to_string(),
+        };
+        write!(f, "{}", str)
+    }
+}
+
+impl From<&str> for Framing {
+    fn from(str: &str) -> Self {
+        match str {
+            NEWLINE => Framing::Newline,
+            LENGTH_PREFIXED => Framing::LengthPrefixed,
+            _ => Framing::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(super) enum Frame {
+    Data(Vec<u8>),
+    // Record is larger than allowed, it is skipped with the given size.
+    TooLarge(usize),
+    // Client asked to close connection.
+    Interrupt,
+}
+
+/// Reads frames from the stream reassembling them from partial reads.
+pub(super) struct FrameReader<R> {
+    reader: R,
+    buf: Vec<u8>,
+    framing: Framing,
+    max_size: usize,
+    eof: bool,
+}
+
+impl<R: AsyncRead + Unpin> FrameReader<R> {
+    pub(super) fn new(reader: R, framing: Framing, max_size: usize) -> Self {
+        Self {
+            reader,
+            buf: Vec::with_capacity(READ_BUFFER_SIZE),
+            framing,
+            max_size,
+            eof: false,
+        }
+    }
+
+    /// Returns data which is read from the stream but not returned as frames yet.
+    pub(super) fn buffer(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Returns true if the next frame is already read to the buffer,
+    /// so it can be returned without waiting for the stream.
+    pub(super) fn has_frame(&self) -> bool {
+        match self.framing {
+            Framing::LengthPrefixed => match self.buf.get(..LENGTH_PREFIX_SIZE) {
+                Some(prefix) => {
+                    let prefix: [u8; LENGTH_PREFIX_SIZE] =
+                        prefix.try_into().expect("slice has correct length");
+                    self.buf.len() >= LENGTH_PREFIX_SIZE + u32::from_be_bytes(prefix) as usize
+                }
+                None => false,
+            },
+            _ => self.buf.contains(&b'\n'),
+        }
+    }
+
+    /// Reads more data from the stream to the buffer.
+    /// Returns false if stream is closed.
+    pub(super) async fn fill(&mut self) -> io::Result<bool> {
+        if self.eof {
+            return Ok(false);
+        }
+        let mut chunk = [0; READ_BUFFER_SIZE];
+        let n = self.reader.read(&mut chunk).await?;
+        if n == 0 {
+            self.eof = true;
+            return Ok(false);
+        }
+        self.buf.extend_from_slice(&chunk[..n]);
+        Ok(true)
+    }
+
+    /// Returns None if stream is closed and there are no more frames.
+    pub(super) async fn next_frame(&mut self) -> io::Result<Option<Frame>> {
+        match self.framing {
+            Framing::LengthPrefixed => self.next_length_prefixed_frame().await,
+            _ => self.next_newline_frame().await,
+        }
+    }
+
+    async fn next_newline_frame(&mut self) -> io::Result<Option<Frame>> {
+        // Position till which we already checked buffer for new line.
+        let mut checked = 0;
+        // Size of the too large frame which was already dropped from the buffer.
+        let mut skipped = 0;
+        loop {
+            if skipped == 0 && self.buf.starts_with(&TELNET_INTERRUPT) {
+                return Ok(Some(Frame::Interrupt));
+            }
+            if let Some(i) = self.buf[checked..].iter().position(|x| *x == b'\n') {
+                let mut data: Vec<u8> = self.buf.drain(..checked + i + 1).collect();
+                data.pop();
+                if data.ends_with(b"\r") {
+                    data.pop();
+                }
+                if skipped > 0 {
+                    return Ok(Some(Frame::TooLarge(skipped + data.len())));
+                }
+                return Ok(Some(check_size(data, self.max_size)));
+            }
+            if self.buf.len() > self.max_size {
+                // We don't want to keep too large frame in memory, so we drop it by parts.
+                skipped += self.buf.len();
+                self.buf.clear();
+            }
+            checked = self.buf.len();
+            if !self.fill().await? {
+                let data = std::mem::take(&mut self.buf);
+                return Ok(match (skipped, data.is_empty()) {
+                    (0, true) => None,
+                    // The last record can be without new line at the end.
+                    (0, false) => Some(check_size(data, self.max_size)),
+                    (_, _) => Some(Frame::TooLarge(skipped + data.len())),
+                });
+            }
+        }
+    }
+
+    async fn next_length_prefixed_frame(&mut self) -> io::Result<Option<Frame>> {
+        if !self.fill_to(LENGTH_PREFIX_SIZE).await? {
+            return self.unexpected_eof();
+        }
+        let prefix: [u8; LENGTH_PREFIX_SIZE] =
+            self.buf[..LENGTH_PREFIX_SIZE].try_into().expect("slice has correct length");
+        let length = u32::from_be_bytes(prefix) as usize;
+        self.buf.drain(..LENGTH_PREFIX_SIZE);
+        if length > self.max_size {
+            // Skip the frame without keeping it in memory.
+            let mut left = length;
+            loop {
+                let n = left.min(self.buf.len());
+                self.buf.drain(..n);
+                left -= n;
+                if left == 0 {
+                    return Ok(Some(Frame::TooLarge(length)));
+                }
+                if !self.fill().await? {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+            }
+        }
+        match self.read_exact(length).await? {
+            Some(data) => Ok(Some(Frame::Data(data))),
+            None => Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+
+    /// Reads line without new line at the end.
+    /// Returns None if stream is closed before the end of the line.
+    pub(super) async fn read_line(&mut self, max_size: usize) -> io::Result<Option<Frame>> {
+        let mut checked = 0;
+        loop {
+            if let Some(i) = self.buf[checked..].iter().position(|x| *x == b'\n') {
+                let mut line: Vec<u8> = self.buf.drain(..checked + i + 1).collect();
+                line.pop();
+                if line.ends_with(b"\r") {
+                    line.pop();
+                }
+                return Ok(Some(check_size(line, max_size)));
+            }
+            // Unlike frames, too large line is not skipped as we can't continue reading after it.
+            if self.buf.len() > max_size {
+                return Ok(Some(Frame::TooLarge(self.buf.len())));
+            }
+            checked = self.buf.len();
+            if !self.fill().await? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Returns None if stream is closed before all data is read.
+    pub(super) async fn read_exact(&mut self, size: usize) -> io::Result<Option<Vec<u8>>> {
+        if !self.fill_to(size).await? {
+            return Ok(None);
+        }
+        Ok(Some(self.buf.drain(..size).collect()))
+    }
+
+    // Returns false if stream is closed before buffer has enough data.
+    async fn fill_to(&mut self, size: usize) -> io::Result<bool> {
+        while self.buf.len() < size {
+            if !self.fill().await? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn unexpected_eof(&self) -> io::Result<Option<Frame>> {
+        if self.buf.is_empty() {
+            return Ok(None);
+        }
+        Err(io::ErrorKind::UnexpectedEof.into())
+    }
+}
+
+fn check_size(data: Vec<u8>, max_size: usize) -> Frame {
+    if data.len() > max_size {
+        return Frame::TooLarge(data.len());
+    }
+    Frame::Data(data)
+}
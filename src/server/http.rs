@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+
+use tokio::io::AsyncRead;
+
+use super::framing::{Frame, FrameReader};
+use super::Error;
+
+pub(super) const HTTP_METHODS: [&str; 7] =
+    ["GET", "HEAD", "POST", "PUT", "DELETE", "OPTIONS", "PATCH"];
+
+const HTTP_VERSION_1_0: &str = "HTTP/1.0";
+const HTTP_VERSION_1_1: &str = "HTTP/1.1";
+
+// Limits for request line and every header line.
+const MAX_LINE_SIZE: usize = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+pub(super) const MAX_BODY_SIZE: usize = 16 * 1024 * 1024; // 16MB
+
+pub(super) struct Request {
+    pub(super) method: String,
+    pub(super) path: String,
+    pub(super) version: String,
+    query: HashMap<String, String>,
+    // Header names are in lower case.
+    headers: HashMap<String, String>,
+    pub(super) body: Vec<u8>,
+}
+
+impl Request {
+    pub(super) fn param(&self, name: &str) -> Option<&str> {
+        self.query.get(name).map(String::as_str)
+    }
+
+    pub(super) fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_ascii_lowercase()).map(String::as_str)
+    }
+
+    /// HTTP/1.1 connections are persistent by default and HTTP/1.0 ones are not.
+    pub(super) fn is_keep_alive(&self) -> bool {
+        let connection = self.header("connection").map(str::to_ascii_lowercase);
+        match connection.as_deref() {
+            Some("close") => false,
+            Some("keep-alive") => true,
+            _ => self.version == HTTP_VERSION_1_1,
+        }
+    }
+}
+
+pub(super) struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    pub(super) fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub(super) fn text(status: u16, text: &str) -> Self {
+        Self::new(status).with_body("text/plain; charset=utf-8", text.as_bytes().to_vec())
+    }
+
+    pub(super) fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub(super) fn with_body(self, content_type: &str, body: Vec<u8>) -> Self {
+        let mut response = self.with_header("Content-Type", content_type);
+        response.body = body;
+        response
+    }
+
+    /// Encodes response with headers for streaming, so it doesn't have body length.
+    pub(super) fn encode_stream_head(&self) -> Vec<u8> {
+        let mut buf = self.encode_head();
+        buf.extend_from_slice(b"\r\n");
+        buf
+    }
+
+    pub(super) fn encode(&self, keep_alive: bool) -> Vec<u8> {
+        let mut buf = self.encode_head();
+        let connection = if keep_alive { "keep-alive" } else { "close" };
+        buf.extend_from_slice(format!("Connection: {}\r\n", connection).as_bytes());
+        buf.extend_from_slice(format!("Content-Length: {}\r\n\r\n", self.body.len()).as_bytes());
+        buf.extend_from_slice(&self.body);
+        buf
+    }
+
+    fn encode_head(&self) -> Vec<u8> {
+        let mut buf = format!("{} {} {}\r\n", HTTP_VERSION_1_1, self.status, reason(self.status))
+            .into_bytes();
+        for (name, value) in &self.headers {
+            buf.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+        }
+        buf
+    }
+}
+
+/// Encodes server-sent event. Every line of the data is sent as a separate data field.
+pub(super) fn sse_event(id: &str, event: &str, data: &[u8]) -> Vec<u8> {
+    let mut buf = format!("id: {}\nevent: {}\n", id, event).into_bytes();
+    for line in data.split(|x| *x == b'\n') {
+        buf.extend_from_slice(b"data: ");
+        buf.extend_from_slice(line.strip_suffix(b"\r").unwrap_or(line));
+        buf.push(b'\n');
+    }
+    buf.push(b'\n');
+    buf
+}
+
+/// Reads the next request from the connection.
+/// Returns None if connection is closed before the next request.
+pub(super) async fn read_request<R: AsyncRead + Unpin>(
+    reader: &mut FrameReader<R>,
+) -> Result<Option<Request>, Error> {
+    let Some(line) = read_line(reader).await? else {
+        return Ok(None);
+    };
+    let (method, target, version) = parse_request_line(&line)?;
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, parse_query(query)?),
+        None => (target, HashMap::new()),
+    };
+
+    let mut headers: HashMap<String, String> = HashMap::new();
+    loop {
+        let line = read_line(reader).await?.ok_or(unexpected_eof())?;
+        if line.is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(Error::BadRequest("too many headers".to_string()));
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| Error::BadRequest(format!("invalid header: {}", line)))?;
+        let name = name.trim().to_ascii_lowercase();
+        let value = value.trim();
+        // Repeated headers are joined by comma as HTTP specification says.
+        headers
+            .entry(name)
+            .and_modify(|x| {
+                x.push_str(", ");
+                x.push_str(value);
+            })
+            .or_insert_with(|| value.to_string());
+    }
+
+    let mut request = Request {
+        method: method.to_string(),
+        path: percent_decode(path, false)?,
+        version: version.to_string(),
+        query,
+        headers,
+        body: Vec::new(),
+    };
+    request.body = read_body(reader, &request).await?;
+    Ok(Some(request))
+}
+
+async fn read_body<R: AsyncRead + Unpin>(
+    reader: &mut FrameReader<R>,
+    request: &Request,
+) -> Result<Vec<u8>, Error> {
+    if let Some(encoding) = request.header("transfer-encoding") {
+        if !encoding.eq_ignore_ascii_case("chunked") {
+            return Err(Error::BadRequest(format!("unsupported transfer encoding: {}", encoding)));
+        }
+        return read_chunked_body(reader).await;
+    }
+    let length = match request.header("content-length") {
+        Some(length) => length
+            .parse::<usize>()
+            .map_err(|_| Error::BadRequest(format!("invalid content length: {}", length)))?,
+        None => return Ok(Vec::new()),
+    };
+    if length > MAX_BODY_SIZE {
+        return Err(Error::PayloadTooLarge(length));
+    }
+    reader.read_exact(length).await?.ok_or(unexpected_eof())
+}
+
+async fn read_chunked_body<R: AsyncRead + Unpin>(
+    reader: &mut FrameReader<R>,
+) -> Result<Vec<u8>, Error> {
+    let mut body: Vec<u8> = Vec::new();
+    loop {
+        let line = read_line(reader).await?.ok_or(unexpected_eof())?;
+        // Chunk size can be followed by extensions which we don't support.
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| Error::BadRequest(format!("invalid chunk size: {}", line)))?;
+        if size == 0 {
+            break;
+        }
+        if body.len() + size > MAX_BODY_SIZE {
+            return Err(Error::PayloadTooLarge(body.len() + size));
+        }
+        body.extend(reader.read_exact(size).await?.ok_or(unexpected_eof())?);
+        if !read_line(reader).await?.ok_or(unexpected_eof())?.is_empty() {
+            return Err(Error::BadRequest("chunk is longer than its size".to_string()));
+        }
+    }
+    // Skip trailer headers.
+    while !read_line(reader).await?.ok_or(unexpected_eof())?.is_empty() {}
+    Ok(body)
+}
+
+async fn read_line<R: AsyncRead + Unpin>(
+    reader: &mut FrameReader<R>,
+) -> Result<Option<String>, Error> {
+    match reader.read_line(MAX_LINE_SIZE).await? {
+        None => Ok(None),
+        Some(Frame::Data(line)) => String::from_utf8(line)
+            .map(Some)
+            .map_err(|_| Error::BadRequest("request is not valid utf-8".to_string())),
+        Some(_) => Err(Error::BadRequest("request line or header is too long".to_string())),
+    }
+}
+
+fn parse_request_line(line: &str) -> Result<(&str, &str, &str), Error> {
+    let mut parts = line.split(' ');
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version), None)
+            if HTTP_METHODS.contains(&method)
+                && target.starts_with('/')
+                && [HTTP_VERSION_1_0, HTTP_VERSION_1_1].contains(&version) =>
+        {
+            Ok((method, target, version))
+        }
+        _ => Err(Error::BadRequest(format!("invalid request line: {}", line))),
+    }
+}
+
+fn parse_query(query: &str) -> Result<HashMap<String, String>, Error> {
+    let mut params: HashMap<String, String> = HashMap::new();
+    for pair in query.split('&').filter(|x| !x.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        params.insert(percent_decode(name, true)?, percent_decode(value, true)?);
+    }
+    Ok(params)
+}
+
+/// Decodes %XX sequences and also + as space in query string.
+pub(super) fn percent_decode(value: &str, is_query: bool) -> Result<String, Error> {
+    let bytes = value.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let byte = value
+                    .get(i + 1..i + 3)
+                    .and_then(|x| u8::from_str_radix(x, 16).ok())
+                    .ok_or_else(|| {
+                        Error::BadRequest(format!("invalid percent encoding: {}", value))
+                    })?;
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+            b'+' if is_query => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8(decoded)
+        .map_err(|_| Error::BadRequest(format!("invalid percent encoding: {}", value)))
+}
+
+fn unexpected_eof() -> Error {
+    Error::BadRequest("request is incomplete".to_string())
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        500 => "Internal Server Error",
+        _ => "Unknown",
+    }
+}
@@ -0,0 +1,1073 @@
+use std::io;
+use std::net::SocketAddr;
+use std::str::from_utf8;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use thiserror::Error;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::watch;
+use tracing::{debug, error, info, trace, warn};
+
+use crate::cluster;
+use crate::index::{self, analyzer::Analyzer, FlattenLimits, Hit};
+use crate::key::min_key;
+use crate::log_storage::{Key, LogStoragePointer, Notifier};
+use crate::parser::{self, Parser};
+use crate::query::Query;
+use crate::shared::now_as_nanos_u64;
+
+use batcher::Batcher;
+use framing::{Frame, FrameReader, Framing};
+use http::{Request, Response, HTTP_METHODS};
+
+mod api;
+mod batcher;
+mod framing;
+mod http;
+
+pub const CMD_CLUSTER: &str = "cluster>";
+
+const SSE_EVENT_LOG: &str = "log";
+// Comment line which is ignored by clients.
+const SSE_PING: &[u8] = b": ping\n\n";
+const SSE_BATCH_SIZE: usize = 1000;
+const SSE_PING_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(10);
+
+#[derive(Clone, Copy)]
+enum Route {
+    Aggregate,
+    Dashboard,
+    Delete,
+    Events,
+    Health,
+    Histogram,
+    Ingest,
+    Search,
+    Stats,
+}
+
+const ROUTES: [(&str, &str, Route); 9] = [
+    ("GET", "/", Route::Dashboard),
+    ("GET", "/events", Route::Events),
+    ("GET", "/health", Route::Health),
+    ("GET", "/api/search", Route::Search),
+    ("GET", "/api/stats", Route::Stats),
+    ("GET", "/api/aggregate", Route::Aggregate),
+    ("GET", "/api/histogram", Route::Histogram),
+    ("POST", "/api/ingest", Route::Ingest),
+    ("DELETE", "/api/logs", Route::Delete),
+];
+
+pub(crate) struct Server {
+    dashboard_content: String,
+    connection_counter: Arc<AtomicU64>,
+    log_storage: LogStoragePointer,
+    csr: cluster::Transmitter,
+    framing: Framing,
+    max_record_size: usize,
+    parser: Arc<Parser>,
+    limits: FlattenLimits,
+    analyzer: Arc<Analyzer>,
+}
+
+impl Server {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        dashboard_content: String,
+        connection_counter: Arc<AtomicU64>,
+        log_storage: LogStoragePointer,
+        csr: cluster::Transmitter,
+        framing: &str,
+        format: &str,
+        max_record_size: usize,
+        limits: FlattenLimits,
+        analyzer: Analyzer,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let framing = Framing::from(framing);
+        if let Framing::Unknown = framing {
+            return Err(format!("unknown ingest framing: {}", framing).into());
+        }
+        let parser = parser::new_parser(format)?;
+        info!(format, "using as an ingest format");
+        Ok(Server {
+            dashboard_content,
+            connection_counter,
+            log_storage,
+            csr,
+            framing,
+            max_record_size,
+            parser: Arc::new(parser),
+            limits,
+            analyzer: Arc::new(analyzer),
+        })
+    }
+
+    pub(crate) async fn start(
+        &self,
+        socket_addr: &str,
+        shutdown_rx: watch::Receiver<()>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let listener = TcpListener::bind(socket_addr).await?;
+        /*
+           We get local address from listener instead of use from &self
+            because we can pass local address with zero port which can be selected randomly.
+        */
+        let local_addr = listener.local_addr()?;
+        info!("socket starts at: {}", local_addr);
+
+        let mut shutdown_rx_ = shutdown_rx.clone();
+        tokio::select! {
+            res = self.accept(listener, shutdown_rx.clone()) => { res }
+            _ = shutdown_rx_.changed() => {
+                debug!("terminating accept new clients loop");
+                Ok(())
+            }
+        }
+    }
+
+    async fn accept(
+        &self,
+        listener: TcpListener,
+        shutdown_rx: watch::Receiver<()>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        loop {
+            let (socket, socket_addr) = listener.accept().await?;
+            info!("new client; ip: {}", socket_addr);
+            self.connection_counter.fetch_add(1, Ordering::Relaxed);
+
+            let mut connection = Connection::new(
+                socket,
+                socket_addr,
+                shutdown_rx.clone(),
+                self.dashboard_content.clone(),
+                self.connection_counter.clone(),
+                self.log_storage.clone(),
+                self.csr.subscribe(),
+                self.framing,
+                self.max_record_size,
+                self.parser.clone(),
+                self.limits,
+                self.analyzer.clone(),
+            );
+            tokio::spawn(async move {
+                trace!("spawn thread for {} client", socket_addr);
+                connection.process_socket().await;
+                trace!("moving from spawn in accept loop for {} client", socket_addr);
+            });
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        trace!("dropping Server")
+    }
+}
+
+struct Connection {
+    reader: FrameReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    socket_addr: SocketAddr,
+    shutdown_rx: watch::Receiver<()>,
+    dashboard_content: String,
+    connection_counter: Arc<AtomicU64>,
+    log_storage: LogStoragePointer,
+    csr: cluster::Reader,
+    parser: Arc<Parser>,
+    limits: FlattenLimits,
+    analyzer: Arc<Analyzer>,
+}
+
+impl Connection {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        socket: TcpStream,
+        socket_addr: SocketAddr,
+        shutdown_rx: watch::Receiver<()>,
+        dashboard_content: String,
+        connection_counter: Arc<AtomicU64>,
+        log_storage: LogStoragePointer,
+        csr: cluster::Reader,
+        framing: Framing,
+        max_record_size: usize,
+        parser: Arc<Parser>,
+        limits: FlattenLimits,
+        analyzer: Arc<Analyzer>,
+    ) -> Self {
+        let (reader, writer) = socket.into_split();
+        Connection {
+            reader: FrameReader::new(reader, framing, max_record_size),
+            writer,
+            socket_addr,
+            shutdown_rx,
+            dashboard_content,
+            connection_counter,
+            log_storage,
+            csr,
+            parser,
+            limits,
+            analyzer,
+        }
+    }
+
+    async fn process_socket(&mut self) {
+        let mut shutdown_rx_ = self.shutdown_rx.clone();
+        tokio::select! {
+            res = self.read_data() => {
+                match res {
+                    Ok(()) => debug!("connection with {} client closed successfully", self.socket_addr),
+                    Err(e) => error!("failed to read data from socket; client: {}: {}", self.socket_addr, e)
+                }
+            }
+            _ = shutdown_rx_.changed() => {
+                trace!("terminating read data loop; client: {}", self.socket_addr);
+            }
+        }
+
+        match self.writer.shutdown().await {
+            Ok(()) => trace!("successfully shutdown {} socket", self.socket_addr),
+            Err(e) => match e.kind() {
+                std::io::ErrorKind::NotConnected => debug!(
+                    "cannot shutdown {} socket because it is already disconnected: {}",
+                    self.socket_addr, e
+                ),
+                _ => error!("failed to shutdown socket with {} address: {}", self.socket_addr, e),
+            },
+        }
+
+        trace!("we have moved from select in Connection.process_socket");
+    }
+
+    async fn read_data(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        match self.process_data().await {
+            Ok(()) => {
+                trace!("close connection with {} client", self.socket_addr);
+                Ok(())
+            }
+            Err(Error::Disconnected(e)) => {
+                debug!("looks like {} client disconnected: {}", &self.socket_addr, e);
+                Ok(())
+            }
+            Err(e) => {
+                error!("failed to process data from {} client: {}", self.socket_addr, e);
+                Err(e.to_string().into())
+            }
+        }
+    }
+
+    async fn process_data(&mut self) -> Result<(), Error> {
+        if !self.sniff().await? {
+            trace!("connection with {} client closed", self.socket_addr);
+            return Ok(());
+        }
+        let buf = self.reader.buffer();
+        if buf.starts_with(CMD_CLUSTER.as_bytes()) {
+            return self.handle_cluster().await;
+        }
+        if is_http(buf) {
+            return self.handle_http().await;
+        }
+        self.handle_logs().await
+    }
+
+    // Reads data until we can detect connection protocol by the first bytes.
+    // Returns false if connection is closed without any data.
+    async fn sniff(&mut self) -> Result<bool, Error> {
+        loop {
+            let buf = self.reader.buffer();
+            if !buf.is_empty() && !is_protocol_prefix(buf) {
+                return Ok(true);
+            }
+            if !self.reader.fill().await? {
+                return Ok(!self.reader.buffer().is_empty());
+            }
+        }
+    }
+
+    async fn handle_http(&mut self) -> Result<(), Error> {
+        loop {
+            let request = match http::read_request(&mut self.reader).await {
+                Ok(Some(request)) => request,
+                Ok(None) => return Ok(()),
+                Err(Error::BadRequest(e)) => {
+                    debug!("bad request from {} client: {}", self.socket_addr, e);
+                    return self.send_response(Response::text(400, &e), false).await;
+                }
+                Err(Error::PayloadTooLarge(size)) => {
+                    debug!("too large request from {} client: {} bytes", self.socket_addr, size);
+                    let response = Response::text(413, "request body is too large");
+                    return self.send_response(response, false).await;
+                }
+                Err(e) => return Err(e),
+            };
+            trace!("{} {} request from {} client", request.method, request.path, self.socket_addr);
+            let keep_alive = request.is_keep_alive();
+            let response = match route(&request) {
+                Ok(Route::Aggregate) => self.handle_aggregate(&request).await,
+                Ok(Route::Dashboard) => self.handle_dashboard(),
+                Ok(Route::Delete) => self.handle_delete(&request).await,
+                // Events are streamed till the end of the connection.
+                Ok(Route::Events) => return self.handle_sse(&request).await,
+                Ok(Route::Health) => self.handle_health(),
+                Ok(Route::Histogram) => self.handle_histogram(&request).await,
+                Ok(Route::Ingest) => self.handle_ingest(&request).await,
+                Ok(Route::Search) => self.handle_search(&request).await,
+                Ok(Route::Stats) => self.handle_stats().await,
+                Err(response) => response,
+            };
+            self.send_response(response, keep_alive).await?;
+            if !keep_alive {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn send_response(&mut self, response: Response, keep_alive: bool) -> Result<(), Error> {
+        write(&mut self.writer, &response.encode(keep_alive), true).await
+    }
+
+    async fn handle_logs(&mut self) -> Result<(), Error> {
+        let mut batch = Batcher::new();
+        loop {
+            // Batch is stored before waiting for more data, so logs are not delayed
+            // while client doesn't send anything.
+            if batch.is_full() || (!batch.is_empty() && !self.reader.has_frame()) {
+                self.handle_batch(batch.take()).await?;
+            }
+            match self.reader.next_frame().await? {
+                None => break,
+                Some(Frame::Interrupt) => {
+                    trace!(
+                        "connection with {} client closed (ctrl+c by telnet client)",
+                        self.socket_addr
+                    );
+                    break;
+                }
+                Some(Frame::TooLarge(size)) => warn!(
+                    "record from {} client is skipped as it is too large: {} bytes",
+                    self.socket_addr, size
+                ),
+                // Empty lines are used by clients to keep connection alive.
+                Some(Frame::Data(data)) if data.is_empty() => (),
+                Some(Frame::Data(data)) => match self.parser.parse(data) {
+                    Ok(data) => batch.push(data),
+                    Err(e) => warn!("record from {} client is skipped: {}", self.socket_addr, e),
+                },
+            }
+        }
+        self.handle_batch(batch.take()).await
+    }
+
+    fn handle_dashboard(&self) -> Response {
+        info!("sent dashboard for {} client", self.socket_addr);
+        Response::new(200)
+            .with_body("text/html; charset=utf-8", self.dashboard_content.as_bytes().to_vec())
+    }
+
+    // Large number of logs is split into batches to not block searches for a long time.
+    async fn store_records(&self, records: Vec<Vec<u8>>) -> Result<(), Error> {
+        let mut batch = Batcher::new();
+        for data in records {
+            batch.push(data);
+            if batch.is_full() {
+                self.handle_batch(batch.take()).await?;
+            }
+        }
+        self.handle_batch(batch.take()).await
+    }
+
+    async fn handle_batch(&self, batch: Vec<Vec<u8>>) -> Result<(), Error> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        debug!("{} logs received from {} client", batch.len(), self.socket_addr);
+        for data in &batch {
+            trace!("new data received from {} client: {:?}", self.socket_addr, from_utf8(data));
+        }
+        self.log_storage.store_batch(batch).await.map_err(map_err)
+    }
+
+    async fn handle_sse(&mut self, request: &Request) -> Result<(), Error> {
+        let params = match api::EventsParams::parse(request, self.limits, &self.analyzer) {
+            Ok(params) => params,
+            Err(e) => return self.send_response(api::error(400, &e), false).await,
+        };
+        // We subscribe before sending stored logs, so no logs are lost between them.
+        let notifier = self.log_storage.subscribe();
+        // Without last event id only new logs are sent.
+        let last_key = match params.last_event_id {
+            Some(key) => key,
+            None => min_key(now_as_nanos_u64().map_err(map_err)?),
+        };
+        let response = Response::new(200)
+            .with_header("Content-Type", "text/event-stream")
+            .with_header("Cache-Control", "no-cache")
+            .with_header("Connection", "keep-alive");
+        write(&mut self.writer, &response.encode_stream_head(), false).await?;
+        write(&mut self.writer, b"retry: 10000\n\n", true).await?;
+        let mut shutdown_rx_ = self.shutdown_rx.clone();
+        let resume = params.last_event_id.is_some();
+        tokio::select! {
+            res = self.send_sse_data(&params, notifier, last_key, resume) => { res },
+            _ = shutdown_rx_.changed() => {
+                trace!("terminating sse send data loop; client: {}", self.socket_addr);
+                Ok(())
+            }
+        }
+    }
+
+    async fn send_sse_data(
+        &mut self,
+        params: &api::EventsParams,
+        mut notifier: Notifier,
+        mut last_key: Key,
+        resume: bool,
+    ) -> Result<(), Error> {
+        // Stored logs up to this key were sent from the history, they can be received again.
+        let mut history_key: Key = 0;
+        if resume {
+            last_key = self.send_sse_history(&params.query, last_key).await?;
+            history_key = last_key;
+        }
+        let mut ping = tokio::time::interval(SSE_PING_INTERVAL);
+        loop {
+            tokio::select! {
+                batch = notifier.recv() => match batch {
+                    Ok(batch) => {
+                        let mut events: Vec<u8> = Vec::new();
+                        for record in batch.iter() {
+                            if record.hit.key <= history_key || !params.matcher.is_match(&record.data) {
+                                continue;
+                            }
+                            events.extend_from_slice(&sse_event(&record.hit, &record.data));
+                            last_key = last_key.max(record.hit.key);
+                        }
+                        if !events.is_empty() {
+                            write(&mut self.writer, &events, true).await?;
+                        }
+                    }
+                    Err(RecvError::Lagged(n)) => {
+                        debug!("{} sse client lagged by {} batches", self.socket_addr, n);
+                        last_key = self.send_sse_history(&params.query, last_key).await?;
+                        history_key = last_key;
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                // We need to send something from time to time to check that connection is open.
+                _ = ping.tick() => write(&mut self.writer, SSE_PING, true).await?,
+            }
+        }
+    }
+
+    // Sends stored logs with keys greater than the given one and returns the last sent key.
+    async fn send_sse_history(&mut self, query: &Query, mut last_key: Key) -> Result<Key, Error> {
+        let mut after = last_key;
+        loop {
+            let result =
+                self.log_storage.tail(query, after, SSE_BATCH_SIZE).await.map_err(map_err)?;
+            for (hit, data) in &result.hits {
+                write(&mut self.writer, &sse_event(hit, data), false).await?;
+                last_key = hit.key;
+            }
+            trace!("sent {} stored sse events to {} client", result.hits.len(), self.socket_addr);
+            // Logs deleted after they were found are skipped, so the page can be shorter.
+            match result.cursor {
+                Some(cursor) => after = cursor.key(),
+                None => {
+                    write(&mut self.writer, &[], true).await?;
+                    return Ok(last_key);
+                }
+            }
+        }
+    }
+
+    fn handle_health(&self) -> Response {
+        Response::new(200)
+    }
+
+    async fn handle_ingest(&self, request: &Request) -> Response {
+        let body = match api::IngestBody::parse(request, &self.parser) {
+            Ok(body) => body,
+            Err(e) => return api::error(400, &e),
+        };
+        let accepted = body.records.len();
+        if let Err(e) = self.store_records(body.records).await {
+            error!("failed to ingest logs for {} client: {}", self.socket_addr, e);
+            return api::error(500, &e.to_string());
+        }
+        let response = api::IngestResponse {
+            accepted,
+            rejected: body.rejected.len(),
+            errors: body.rejected,
+        };
+        api::json(200, &response)
+    }
+
+    async fn handle_search(&self, request: &Request) -> Response {
+        let params = match api::SearchParams::parse(request) {
+            Ok(params) => params,
+            Err(e) => return api::error(400, &e),
+        };
+        let res = self.log_storage.search(&params.query, &params.options).await;
+        match res {
+            Ok(result) => api::json(200, &api::SearchResponse::from(result)),
+            Err(e) => {
+                error!("failed to search logs for {} client: {}", self.socket_addr, e);
+                api::error(error_status(e.as_ref()), &e.to_string())
+            }
+        }
+    }
+
+    async fn handle_aggregate(&self, request: &Request) -> Response {
+        let params = match api::AggregateParams::parse(request) {
+            Ok(params) => params,
+            Err(e) => return api::error(400, &e),
+        };
+        let res =
+            self.log_storage.aggregate(&params.query, &params.options, &params.aggregation).await;
+        match res {
+            Ok(result) => api::json(200, &api::AggregateResponse::from(result)),
+            Err(e) => {
+                error!("failed to aggregate logs for {} client: {}", self.socket_addr, e);
+                api::error(error_status(e.as_ref()), &e.to_string())
+            }
+        }
+    }
+
+    async fn handle_histogram(&self, request: &Request) -> Response {
+        let params = match api::HistogramParams::parse(request) {
+            Ok(params) => params,
+            Err(e) => return api::error(400, &e),
+        };
+        match self.log_storage.histogram(&params.query, &params.histogram).await {
+            Ok(buckets) => api::json(200, &api::HistogramResponse::new(&params.histogram, buckets)),
+            Err(e) => {
+                error!("failed to build histogram for {} client: {}", self.socket_addr, e);
+                api::error(error_status(e.as_ref()), &e.to_string())
+            }
+        }
+    }
+
+    async fn handle_delete(&self, request: &Request) -> Response {
+        let params = match api::DeleteParams::parse(request) {
+            Ok(params) => params,
+            Err(e) => return api::error(400, &e),
+        };
+        let res = self.log_storage.delete_by_query(&params.query, params.dry_run).await;
+        match res {
+            Ok(deleted) => {
+                if !params.dry_run {
+                    info!("{} logs are deleted by {} client", deleted, self.socket_addr);
+                }
+                let response = api::DeleteResponse {
+                    deleted,
+                    dry_run: params.dry_run,
+                };
+                api::json(200, &response)
+            }
+            Err(e) => {
+                error!("failed to delete logs for {} client: {}", self.socket_addr, e);
+                api::error(error_status(e.as_ref()), &e.to_string())
+            }
+        }
+    }
+
+    async fn handle_stats(&self) -> Response {
+        match self.log_storage.stats() {
+            Ok(stats) => api::json(200, &api::StatsResponse::from(stats)),
+            Err(e) => {
+                error!("failed to get stats for {} client: {}", self.socket_addr, e);
+                api::error(500, &e.to_string())
+            }
+        }
+    }
+
+    async fn handle_cluster(&mut self) -> Result<(), Error> {
+        loop {
+            let msg = self.csr.recv().await.map_err(map_err)?;
+            write(&mut self.writer, &msg.encode(), true).await?;
+        }
+    }
+}
+
+async fn write(
+    socket: &mut (impl AsyncWrite + Unpin),
+    data: &[u8],
+    flush: bool,
+) -> Result<(), Error> {
+    if !data.is_empty() {
+        match socket.write_all(data).await {
+            Ok(_) => (),
+            Err(e) => match e.kind() {
+                std::io::ErrorKind::BrokenPipe => return Err(Error::Disconnected(e.to_string())),
+                _ => return Err(e.into()),
+            },
+        };
+    }
+    if flush {
+        socket.flush().await?;
+    }
+    Ok(())
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        trace!("dropping Connection for {} client", self.socket_addr);
+        self.connection_counter
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| Some(x - 1))
+            .unwrap();
+    }
+}
+
+// Events are identified by keys as keys of new logs are increasing unlike their times.
+fn sse_event(hit: &Hit, data: &[u8]) -> Vec<u8> {
+    http::sse_event(&hit.key.to_string(), SSE_EVENT_LOG, data)
+}
+
+// Returns response with error if there is no such route.
+fn route(request: &Request) -> Result<Route, Response> {
+    let mut routes = ROUTES.iter().filter(|(_, path, _)| *path == request.path).peekable();
+    if routes.peek().is_none() {
+        return Err(Response::text(404, "not found"));
+    }
+    match routes.find(|(method, _, _)| *method == request.method) {
+        Some((_, _, route)) => Ok(*route),
+        None => Err(Response::text(405, "method not allowed")),
+    }
+}
+
+fn is_http(buf: &[u8]) -> bool {
+    HTTP_METHODS.iter().any(|x| buf.starts_with(x.as_bytes()) && buf.get(x.len()) == Some(&b' '))
+}
+
+// Returns true if data is too short to detect whether it is a command or http request.
+fn is_protocol_prefix(buf: &[u8]) -> bool {
+    let is_prefix = |x: &[u8]| buf.len() < x.len() && x.starts_with(buf);
+    is_prefix(CMD_CLUSTER.as_bytes())
+        || HTTP_METHODS.iter().any(|x| is_prefix(format!("{} ", x).as_bytes()))
+}
+
+#[derive(Error, Debug)]
+enum Error {
+    #[error("cannot send data; looks like {0} client disconnected")]
+    Disconnected(String),
+    #[error("io error")]
+    IO(#[from] io::Error),
+    #[error("internal error: {0}")]
+    Internal(String),
+    #[error("bad request: {0}")]
+    BadRequest(String),
+    #[error("payload is too large: {0} bytes")]
+    PayloadTooLarge(usize),
+}
+
+// Query which the index can't evaluate is the client error.
+fn error_status(err: &(dyn std::error::Error + 'static)) -> u16 {
+    match err.downcast_ref::<index::error::Error>() {
+        Some(index::error::Error::Unsupported(_)) => 400,
+        _ => 500,
+    }
+}
+
+fn map_err<T: ToString>(err: T) -> Error {
+    Error::Internal(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::Arc;
+
+    use flate2::{write::GzEncoder, Compression};
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt, DuplexStream};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::api::{AggregateParams, HistogramParams, HistogramResponse, IngestBody};
+    use super::framing::{Frame, FrameReader, Framing};
+    use super::http::{self, Request};
+    use super::{route, Error, Route, CMD_CLUSTER};
+    use crate::cluster::{self, Message};
+    use crate::config::Config;
+    use crate::index::aggregation::{Aggregation, Histogram, HistogramBucket, ValueCounts};
+    use crate::index::value::{Number, Value};
+    use crate::index::{FindOptions, Hit};
+    use crate::log_storage::{LogStorage, Record};
+    use crate::parser::new_parser;
+    use crate::query;
+
+    // Small buffer makes every read return only a part of the data.
+    const DUPLEX_BUFFER_SIZE: usize = 3;
+
+    #[tokio::test]
+    async fn test_newline_framing() {
+        let data = b"{\"a\":1}\n{\"b\":2}\r\n\n{\"c\":\"too large\"}\n{\"d\":4}".to_vec();
+        let mut reader = FrameReader::new(stream(data), Framing::Newline, 10);
+        assert_eq!(Some(Frame::Data(b"{\"a\":1}".to_vec())), next(&mut reader).await);
+        assert_eq!(Some(Frame::Data(b"{\"b\":2}".to_vec())), next(&mut reader).await);
+        assert_eq!(Some(Frame::Data(vec![])), next(&mut reader).await);
+        assert_eq!(Some(Frame::TooLarge(17)), next(&mut reader).await);
+        // The last record is returned without new line at the end.
+        assert_eq!(Some(Frame::Data(b"{\"d\":4}".to_vec())), next(&mut reader).await);
+        assert_eq!(None, next(&mut reader).await);
+    }
+
+    #[tokio::test]
+    async fn test_newline_framing_interrupt() {
+        let mut data = b"{\"a\":1}\n".to_vec();
+        data.extend_from_slice(&[255, 244, 255, 253, 6]);
+        let mut reader = FrameReader::new(stream(data), Framing::Newline, 10);
+        assert_eq!(Some(Frame::Data(b"{\"a\":1}".to_vec())), next(&mut reader).await);
+        assert_eq!(Some(Frame::Interrupt), next(&mut reader).await);
+    }
+
+    #[tokio::test]
+    async fn test_length_prefixed_framing() {
+        let mut data: Vec<u8> = Vec::new();
+        for record in [&b"{\"a\":\"new\nline\"}"[..], b"{\"b\":\"too large\"}", b""] {
+            data.extend_from_slice(&(record.len() as u32).to_be_bytes());
+            data.extend_from_slice(record);
+        }
+        let mut reader = FrameReader::new(stream(data.clone()), Framing::LengthPrefixed, 16);
+        assert_eq!(Some(Frame::Data(b"{\"a\":\"new\nline\"}".to_vec())), next(&mut reader).await);
+        assert_eq!(Some(Frame::TooLarge(17)), next(&mut reader).await);
+        assert_eq!(Some(Frame::Data(vec![])), next(&mut reader).await);
+        assert_eq!(None, next(&mut reader).await);
+
+        // Stream is closed in the middle of the record.
+        data.extend_from_slice(&[0, 0, 0, 5, 1]);
+        let mut reader = FrameReader::new(stream(data), Framing::LengthPrefixed, 16);
+        for _ in 0..3 {
+            assert!(reader.next_frame().await.unwrap().is_some());
+        }
+        assert!(reader.next_frame().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_has_frame() {
+        let data = b"{\"a\":1}\n{\"b\":2}";
+        let mut reader = FrameReader::new(&data[..], Framing::Newline, 10);
+        assert!(!reader.has_frame());
+        assert!(reader.fill().await.unwrap());
+        assert!(reader.has_frame());
+        assert_eq!(Some(Frame::Data(b"{\"a\":1}".to_vec())), next(&mut reader).await);
+        assert!(!reader.has_frame());
+
+        let data = [0, 0, 0, 2, 1, 2, 0, 0, 0, 2, 1];
+        let mut reader = FrameReader::new(&data[..], Framing::LengthPrefixed, 10);
+        assert!(reader.fill().await.unwrap());
+        assert!(reader.has_frame());
+        assert_eq!(Some(Frame::Data(vec![1, 2])), next(&mut reader).await);
+        assert!(!reader.has_frame());
+    }
+
+    #[tokio::test]
+    async fn test_http_request() {
+        let data =
+            b"GET /events?q=level%3Adebug+OR+a:b&x HTTP/1.1\r\nHost: a\r\nX-A: 1\r\nx-a: 2\r\n\r\n\
+            POST /api%2Fx HTTP/1.0\r\nContent-Length: 5\r\n\r\nhello\
+            PUT /c HTTP/1.1\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n\
+            3\r\nabc\r\n2;ext\r\nde\r\n0\r\n\r\n";
+        let mut reader = FrameReader::new(stream(data.to_vec()), Framing::Newline, 0);
+
+        let request = read_request(&mut reader).await;
+        assert_eq!(("GET", "/events"), (request.method.as_str(), request.path.as_str()));
+        assert_eq!(Some("level:debug OR a:b"), request.param("q"));
+        assert_eq!(Some(""), request.param("x"));
+        assert_eq!(Some("1, 2"), request.header("x-a"));
+        assert!(request.is_keep_alive());
+        assert!(request.body.is_empty());
+
+        let request = read_request(&mut reader).await;
+        assert_eq!(("POST", "/api/x"), (request.method.as_str(), request.path.as_str()));
+        assert_eq!(b"hello".to_vec(), request.body);
+        assert!(!request.is_keep_alive());
+
+        let request = read_request(&mut reader).await;
+        assert_eq!(b"abcde".to_vec(), request.body);
+        assert!(!request.is_keep_alive());
+
+        assert!(http::read_request(&mut reader).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ingest_body() {
+        let json = new_parser("json").unwrap();
+        let parse = |request: &Request| IngestBody::parse(request, &json);
+        let lines = |body: &IngestBody| body.rejected.iter().map(|x| x.line).collect::<Vec<_>>();
+        let ndjson = b"{\"a\":1}\r\n\n[1]\n{\"b\":\n{\"c\":3}";
+        let body = parse(&ingest_request("", "", ndjson).await).unwrap();
+        assert_eq!(vec![b"{\"a\":1}".to_vec(), b"{\"c\":3}".to_vec()], body.records);
+        assert_eq!(vec![3, 4], lines(&body));
+
+        let array = b" [{\"a\":1}, \"b\", {\"c\":3}]";
+        let body = parse(&ingest_request("", "", array).await).unwrap();
+        assert_eq!(vec![b"{\"a\":1}".to_vec(), b"{\"c\":3}".to_vec()], body.records);
+        assert_eq!(vec![2], lines(&body));
+        assert!(parse(&ingest_request("?format=json", "", b"[{}").await).is_err());
+        // Raw lines can look like an array.
+        let body = parse(&ingest_request("?format=raw", "", b"[a] b\n[c]").await).unwrap();
+        assert_eq!(
+            vec![
+                br#"{"message":"[a] b"}"#.to_vec(),
+                br#"{"message":"[c]"}"#.to_vec()
+            ],
+            body.records
+        );
+        assert!(parse(&ingest_request("?format=xml", "", b"").await).is_err());
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(ndjson).unwrap();
+        let gzip = encoder.finish().unwrap();
+        let request = ingest_request("", "Content-Encoding: gzip\r\n", &gzip).await;
+        assert_eq!(2, parse(&request).unwrap().records.len());
+        let request = ingest_request("", "Content-Encoding: gzip\r\n", ndjson).await;
+        assert!(parse(&request).is_err());
+        let request = ingest_request("", "Content-Encoding: br\r\n", ndjson).await;
+        assert!(parse(&request).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_replicate_pretty_json() {
+        let json = new_parser("json").unwrap();
+        let pretty = b"[\n  {\n    \"level\": \"info\",\r\n    \"a\": [1, 2]\n  }\n]";
+        let body = IngestBody::parse(&ingest_request("", "", pretty).await, &json).unwrap();
+        assert_eq!(vec![br#"{    "level": "info",    "a": [1, 2]  }"#.to_vec()], body.records);
+        let records = body
+            .records
+            .into_iter()
+            .map(|data| Record {
+                hit: Hit {
+                    timestamp: 0,
+                    key: 1,
+                },
+                data,
+            })
+            .collect();
+        let message = Message::NewLogs(Arc::new(records)).encode();
+
+        // Origin node sends the message to the replica, which listens to the cluster connection.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut cmd = vec![0; CMD_CLUSTER.len()];
+            stream.read_exact(&mut cmd).await.unwrap();
+            stream.write_all(&message).await.unwrap();
+        });
+        let log_storage = Arc::new(LogStorage::new(&config()).unwrap().0);
+        let stream = TcpStream::connect(addr).await.unwrap();
+        cluster::listen(stream, log_storage.clone()).await.unwrap();
+
+        let query = query::parse("level:info AND a:2").unwrap();
+        let result = log_storage.search(&query, &FindOptions::default()).await.unwrap();
+        assert_eq!(1, result.total);
+        assert_eq!(1, log_storage.stats().unwrap().records);
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_params() {
+        let parse = |query: &'static str| async move {
+            let data = format!("GET /api/aggregate?q=a:1&{} HTTP/1.1\r\n\r\n", query);
+            let mut reader = FrameReader::new(stream(data.into_bytes()), Framing::Newline, 0);
+            AggregateParams::parse(&read_request(&mut reader).await).map(|x| x.aggregation)
+        };
+        assert_eq!(Ok(Aggregation::Count), parse("from=1").await);
+        assert_eq!(
+            Ok(Aggregation::Terms {
+                field: "component".to_string(),
+                size: 10
+            }),
+            parse("type=terms&field=component").await
+        );
+        assert_eq!(
+            Ok(Aggregation::Percentiles {
+                field: "latency".to_string(),
+                percents: vec![50.0, 99.9]
+            }),
+            parse("type=percentiles&field=latency&percents=50,99.9").await
+        );
+        for (query, error) in [
+            ("type=stats", "field parameter is required"),
+            ("type=terms&field=a&size=0", "size should be from 1 to 1000"),
+            ("type=percentiles&field=a&percents=101", "invalid percent: 101"),
+            ("type=sum&field=a", "unknown aggregation type: sum"),
+        ] {
+            assert_eq!(Err(error.to_string()), parse(query).await, "query: {}", query);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_histogram_params() {
+        let parse = |query: &'static str| async move {
+            let data = format!("GET /api/histogram?q=a:1&{} HTTP/1.1\r\n\r\n", query);
+            let mut reader = FrameReader::new(stream(data.into_bytes()), Framing::Newline, 0);
+            HistogramParams::parse(&read_request(&mut reader).await).map(|x| x.histogram)
+        };
+        let minute = 60_000_000_000;
+        let histogram = parse("to=10000000000000").await.unwrap();
+        assert_eq!(
+            (10_000_000_000_000 - 60 * minute, minute),
+            (histogram.from, histogram.interval)
+        );
+        let histogram = parse("interval=500ms&from=1&to=2000000000&split=level").await.unwrap();
+        assert_eq!((500_000_000, Some("level".to_string())), (histogram.interval, histogram.split));
+        assert_eq!(2 * 60 * minute, parse("interval=2h").await.unwrap().interval);
+        assert_eq!(24 * 60 * minute, parse("interval=1d").await.unwrap().interval);
+        for (query, error) in [
+            ("interval=1", "invalid interval: 1"),
+            ("interval=m", "invalid interval: m"),
+            ("interval=0s", "invalid interval: 0s"),
+            ("interval=1w", "invalid interval: 1w"),
+            ("from=2&to=1", "from should be before to"),
+            (
+                "interval=1s&from=0&to=100000000000000",
+                "histogram can't have more than 10000 buckets, increase interval",
+            ),
+        ] {
+            assert_eq!(Err(error.to_string()), parse(query).await, "query: {}", query);
+        }
+    }
+
+    #[test]
+    fn test_histogram_response() {
+        let histogram = Histogram {
+            from: 0,
+            to: 120,
+            interval: 60,
+            split: Some("code".to_string()),
+        };
+        let split = ValueCounts::from([
+            (Value::Number(Number::Integer(1)), 2),
+            (Value::String("1".to_string()), 3),
+            (Value::String("a".to_string()), 1),
+        ]);
+        let buckets = vec![
+            HistogramBucket {
+                start: 0,
+                count: 6,
+                split,
+            },
+            HistogramBucket {
+                start: 60,
+                ..HistogramBucket::default()
+            },
+        ];
+        let response = serde_json::to_value(HistogramResponse::new(&histogram, buckets)).unwrap();
+        // Values with the same string are counted together instead of overwriting each other.
+        assert_eq!(
+            serde_json::json!({
+                "interval": 60,
+                "buckets": [
+                    {"start": 0, "count": 6, "split": {"1": 5, "a": 1}},
+                    {"start": 60, "count": 0, "split": {}},
+                ],
+            }),
+            response
+        );
+    }
+
+    #[tokio::test]
+    async fn test_http_errors() {
+        for data in [
+            &b"GET /\r\n\r\n"[..],
+            b"FETCH / HTTP/1.1\r\n\r\n",
+            b"GET / HTTP/2\r\n\r\n",
+            b"GET /%zz HTTP/1.1\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost: a\r\n",
+            b"POST / HTTP/1.1\r\nContent-Length: x\r\n\r\n",
+            b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nabc",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabcd\r\n0\r\n\r\n",
+        ] {
+            let mut reader = FrameReader::new(stream(data.to_vec()), Framing::Newline, 0);
+            let res = http::read_request(&mut reader).await;
+            assert!(matches!(res, Err(Error::BadRequest(_))), "{}", String::from_utf8_lossy(data));
+        }
+        let data = b"POST / HTTP/1.1\r\nContent-Length: 100000000\r\n\r\n".to_vec();
+        let mut reader = FrameReader::new(stream(data), Framing::Newline, 0);
+        let res = http::read_request(&mut reader).await;
+        assert!(matches!(res, Err(Error::PayloadTooLarge(100000000))));
+    }
+
+    #[tokio::test]
+    async fn test_route() {
+        let data =
+            b"GET /health HTTP/1.1\r\n\r\nPOST /health HTTP/1.1\r\n\r\nGET /x HTTP/1.1\r\n\r\n";
+        let mut reader = FrameReader::new(stream(data.to_vec()), Framing::Newline, 0);
+        assert!(matches!(route(&read_request(&mut reader).await), Ok(Route::Health)));
+        let response = route(&read_request(&mut reader).await).err().unwrap();
+        assert!(response.encode(false).starts_with(b"HTTP/1.1 405 Method Not Allowed\r\n"));
+        let response = route(&read_request(&mut reader).await).err().unwrap();
+        assert!(response.encode(true).starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_sse_event() {
+        assert_eq!(
+            b"id: 1-2\nevent: log\ndata: {\"a\":\ndata: 1}\n\n".to_vec(),
+            http::sse_event("1-2", "log", b"{\"a\":\r\n1}")
+        );
+    }
+
+    fn stream(data: Vec<u8>) -> DuplexStream {
+        let (mut client, server) = tokio::io::duplex(DUPLEX_BUFFER_SIZE);
+        tokio::spawn(async move {
+            client.write_all(&data).await.unwrap();
+        });
+        server
+    }
+
+    async fn next<R: AsyncRead + Unpin>(reader: &mut FrameReader<R>) -> Option<Frame> {
+        reader.next_frame().await.unwrap()
+    }
+
+    async fn ingest_request(query: &str, headers: &str, body: &[u8]) -> Request {
+        let mut data = format!(
+            "POST /api/ingest{} HTTP/1.1\r\n{}Content-Length: {}\r\n\r\n",
+            query,
+            headers,
+            body.len()
+        )
+        .into_bytes();
+        data.extend_from_slice(body);
+        read_request(&mut FrameReader::new(stream(data), Framing::Newline, 0)).await
+    }
+
+    async fn read_request(reader: &mut FrameReader<DuplexStream>) -> Request {
+        http::read_request(reader).await.unwrap().unwrap()
+    }
+
+    fn config() -> Config {
+        Config {
+            socket_addr: String::new(),
+            index_name: "nonsense".to_string(),
+            index_path: String::new(),
+            index_max_depth: 0,
+            index_max_fields: 0,
+            index_checkpoint_interval: 0,
+            text_fields: String::new(),
+            text_stemming: false,
+            text_stop_words: String::new(),
+            storage_name: "in_memory".to_string(),
+            cluster_addrs: String::new(),
+            node_id: 0,
+            storage_path: String::new(),
+            storage_segment_size: 0,
+            storage_compression: "none".to_string(),
+            ingest_framing: String::new(),
+            ingest_format: String::new(),
+            ingest_max_record_size: 0,
+            timestamp_field: String::new(),
+            timestamp_formats: String::new(),
+            retention_max_age: 0,
+            retention_max_bytes: 0,
+            retention_max_records: 0,
+            retention_interval: 0,
+        }
+    }
+}
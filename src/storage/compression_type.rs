@@ -0,0 +1,37 @@
+use std::fmt::{Display, Formatter};
+
+const UNKNOWN: &str = "unknown";
+const NONE: &str = "none";
+const ZSTD: &str = "zstd";
+const LZ4: &str = "lz4";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CompressionType {
+    Unknown,
+    None,
+    Zstd,
+    Lz4,
+}
+
+impl Display for CompressionType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            CompressionType::Unknown => UNKNOWN.to_string(),
+            CompressionType::None => NONE.to_string(),
+            CompressionType::Zstd => ZSTD.to_string(),
+            CompressionType::Lz4 => LZ4.to_string(),
+        };
+        write!(f, "{}", str)
+    }
+}
+
+impl From<&str> for CompressionType {
+    fn from(str: &str) -> Self {
+        match str {
+            NONE => CompressionType::None,
+            ZSTD => CompressionType::Zstd,
+            LZ4 => CompressionType::Lz4,
+            _ => CompressionType::Unknown,
+        }
+    }
+}
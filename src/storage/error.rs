@@ -1,9 +1,21 @@
+use std::io;
+
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub(crate) enum Error {
     #[error("unknown storage type: {0}")]
     UnknownStorageType(String),
+    #[error("unknown compression type: {0}")]
+    UnknownCompressionType(String),
     #[error("not found")]
     NotFound,
+    #[error("io error: {0}")]
+    IO(#[from] io::Error),
+    #[error("storage is corrupted: {0}")]
+    Corrupted(String),
+    #[error("record is too large: {0} bytes")]
+    TooLarge(usize),
+    #[error("compression error: {0}")]
+    Compression(String),
 }
@@ -1,25 +1,746 @@
-use crate::{log_storage::Key, storage::_Storage};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{BufReader, BufWriter, Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 
+use tracing::{debug, warn};
+
+use crate::{
+    log_storage::Key,
+    storage::{KeysResult, ListResult, Stats, _Storage},
+};
+
+use super::compression_type::CompressionType;
 use super::error::Error;
 
-pub(super) struct File {}
+const SEGMENT_EXTENSION: &str = "log";
+const ZSTD_SEGMENT_EXTENSION: &str = "zst";
+const LZ4_SEGMENT_EXTENSION: &str = "lz4";
+// Rewritten segment is written to the temporary file first and renamed when it is complete.
+const TEMP_EXTENSION: &str = "tmp";
+// key (8 bytes) + data length (4 bytes) + checksum (4 bytes).
+const HEADER_LENGTH: usize = 16;
+// Data length of the record which marks the key as deleted.
+const TOMBSTONE_LENGTH: u32 = u32::MAX;
+// raw length (4 bytes) + compressed length (4 bytes) + checksum (4 bytes).
+const BLOCK_HEADER_LENGTH: usize = 12;
+// Records are compressed in blocks, so only one block is decompressed to read a record.
+const BLOCK_SIZE: usize = 64 * 1024;
+const ZSTD_LEVEL: i32 = 3;
+
+type SegmentId = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Segment {
+    id: SegmentId,
+    compression: CompressionType,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Location {
+    segment: SegmentId,
+    // Offset of the block in the compressed segment.
+    block: Option<u64>,
+    // Offset of the data in the raw segment or in the decompressed block.
+    offset: u64,
+    length: u32,
+}
+
+// Size of the segment records and of the segment file.
+#[derive(Default, Clone, Copy)]
+struct SegmentSize {
+    raw: u64,
+    disk: u64,
+}
+
+struct Header {
+    key: Key,
+    length: u32,
+    checksum: u32,
+}
+
+impl Header {
+    fn encode(&self) -> [u8; HEADER_LENGTH] {
+        let mut buf = [0; HEADER_LENGTH];
+        buf[0..8].copy_from_slice(&self.key.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.length.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.checksum.to_le_bytes());
+        buf
+    }
+
+    fn decode(buf: &[u8; HEADER_LENGTH]) -> Self {
+        Self {
+            key: Key::from_le_bytes(buf[0..8].try_into().expect("slice has correct length")),
+            length: u32::from_le_bytes(buf[8..12].try_into().expect("slice has correct length")),
+            checksum: u32::from_le_bytes(buf[12..16].try_into().expect("slice has correct length")),
+        }
+    }
+}
+
+/// Append-only storage which writes records to segment files inside a directory.
+/// Every record is prefixed by a header with its key, length and checksum
+/// and new segment is started when the current one exceeds the configured size.
+/// Deleted records are marked by tombstones and the oldest segments
+/// are removed when there are no live records in them.
+/// Sealed segments are rewritten in compressed blocks without deleted records,
+/// other segments are rewritten without them only when deleted data is purged.
+pub(super) struct File {
+    path: PathBuf,
+    segment_size: u64,
+    compression: CompressionType,
+    segments: Vec<Segment>,
+    active: fs::File,
+    active_length: u64,
+    offsets: BTreeMap<Key, Location>,
+    // Number of live records in every segment.
+    live: HashMap<SegmentId, u64>,
+    sizes: HashMap<SegmentId, SegmentSize>,
+    // Segments which still have data of deleted or overwritten records.
+    garbage: HashSet<SegmentId>,
+    bytes: u64,
+}
 
 impl File {
-    pub(super) fn new() -> Result<Self, Error> {
-        todo!()
+    pub(super) fn new(
+        path: &str,
+        segment_size: u64,
+        compression: CompressionType,
+    ) -> Result<Self, Error> {
+        let path = PathBuf::from(path);
+        fs::create_dir_all(&path)?;
+
+        let mut segments = list_segments(&path)?;
+        let mut offsets: BTreeMap<Key, Location> = BTreeMap::new();
+        let mut sizes: HashMap<SegmentId, SegmentSize> = HashMap::new();
+        for (i, segment) in segments.iter().enumerate() {
+            let is_last = i == segments.len() - 1;
+            let raw = load_segment(&path, *segment, is_last, &mut offsets)?;
+            let disk = fs::metadata(segment_path(&path, *segment))?.len();
+            sizes.insert(segment.id, SegmentSize { raw, disk });
+        }
+
+        // New records are appended only to the raw segment.
+        match segments.last() {
+            Some(segment) if segment.compression == CompressionType::None => (),
+            last => segments.push(Segment {
+                id: last.map_or(0, |x| x.id + 1),
+                compression: CompressionType::None,
+            }),
+        }
+        let active_segment = *segments.last().expect("there is at least one segment");
+        let active = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(segment_path(&path, active_segment))?;
+        let active_length = active.metadata()?.len();
+        debug!(segments = segments.len(), records = offsets.len(), "file storage is loaded");
+
+        let mut live: HashMap<SegmentId, u64> = HashMap::new();
+        let mut bytes = 0;
+        for location in offsets.values() {
+            *live.entry(location.segment).or_default() += 1;
+            bytes += location.length as u64;
+        }
+        let mut storage = Self {
+            path,
+            segment_size,
+            compression,
+            segments,
+            active,
+            active_length,
+            offsets,
+            live,
+            sizes,
+            garbage: HashSet::new(),
+            bytes,
+        };
+        storage.remove_empty_segments()?;
+        // Segments can be sealed before compression was enabled.
+        for i in 0..storage.segments.len() - 1 {
+            storage.compress_segment(i)?;
+        }
+        Ok(storage)
+    }
+
+    fn active_segment(&self) -> Segment {
+        *self.segments.last().expect("there is at least one segment")
+    }
+
+    // Appends the record to the active segment and returns its data offset.
+    fn append(&mut self, header: Header, data: &[u8]) -> Result<Location, Error> {
+        let record_length = (HEADER_LENGTH + data.len()) as u64;
+        if self.active_length > 0 && self.active_length + record_length > self.segment_size {
+            self.rotate()?;
+        }
+        // We build the whole record first to write it with a single call,
+        // so a crash leaves at most one torn record at the end of the segment.
+        let mut record: Vec<u8> = Vec::with_capacity(HEADER_LENGTH + data.len());
+        record.extend_from_slice(&header.encode());
+        record.extend_from_slice(data);
+        self.active.write_all(&record)?;
+
+        let location = Location {
+            segment: self.active_segment().id,
+            block: None,
+            offset: self.active_length + HEADER_LENGTH as u64,
+            length: header.length,
+        };
+        self.active_length += record_length;
+        Ok(location)
+    }
+
+    fn unlink(&mut self, location: Location) {
+        if let Some(live) = self.live.get_mut(&location.segment) {
+            *live -= 1;
+        }
+        self.garbage.insert(location.segment);
+        self.bytes -= location.length as u64;
+    }
+
+    // Only the oldest segments are removed, because their records
+    // can be deleted by tombstones from the next segments.
+    fn remove_empty_segments(&mut self) -> Result<(), Error> {
+        while self.segments.len() > 1 {
+            let segment = self.segments[0];
+            if self.live.get(&segment.id).copied().unwrap_or_default() > 0 {
+                break;
+            }
+            fs::remove_file(segment_path(&self.path, segment))?;
+            self.segments.remove(0);
+            self.live.remove(&segment.id);
+            self.sizes.remove(&segment.id);
+            self.garbage.remove(&segment.id);
+            debug!(segment = segment.id, "segment without live records is removed");
+        }
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<(), Error> {
+        let sealed = self.active_segment();
+        let segment = Segment {
+            id: sealed.id + 1,
+            compression: CompressionType::None,
+        };
+        self.active =
+            OpenOptions::new().create(true).append(true).open(segment_path(&self.path, segment))?;
+        self.sizes.insert(
+            sealed.id,
+            SegmentSize {
+                raw: self.active_length,
+                disk: self.active_length,
+            },
+        );
+        self.active_length = 0;
+        self.segments.push(segment);
+        debug!(segment = segment.id, "new segment is started");
+        self.compress_segment(self.segments.len() - 2)
+    }
+
+    // Rewrites the sealed raw segment in compressed blocks.
+    fn compress_segment(&mut self, i: usize) -> Result<(), Error> {
+        let segment = self.segments[i];
+        if self.compression == CompressionType::None || segment.compression != CompressionType::None
+        {
+            return Ok(());
+        }
+        let size = self.rewrite_segment(i, self.compression)?;
+        debug!(segment = segment.id, raw = size.raw, disk = size.disk, "segment is compressed");
+        Ok(())
+    }
+
+    // Rewrites the segment with the given compression without deleted and overwritten records.
+    // Tombstones are kept as they can delete records from previous segments.
+    fn rewrite_segment(
+        &mut self,
+        i: usize,
+        compression: CompressionType,
+    ) -> Result<SegmentSize, Error> {
+        let segment = self.segments[i];
+        let rewritten = Segment {
+            id: segment.id,
+            compression,
+        };
+        let temp_path = self.path.join(format!("{:020}.{}", segment.id, TEMP_EXTENSION));
+        let mut writer = BufWriter::new(fs::File::create(&temp_path)?);
+        let mut reader = SegmentReader::open(&self.path, segment)?;
+        let mut size = SegmentSize::default();
+        let mut block: Vec<u8> = Vec::with_capacity(BLOCK_SIZE);
+        let mut moved: Vec<(Key, Location)> = Vec::new();
+        while let Some((location, header, data)) = reader.next_record()? {
+            if self.offsets.get(&header.key) == Some(&location) {
+                let offset = (block.len() + HEADER_LENGTH) as u64;
+                let location = match compression {
+                    CompressionType::None => Location {
+                        segment: segment.id,
+                        block: None,
+                        offset: size.raw + offset,
+                        length: header.length,
+                    },
+                    _ => Location {
+                        segment: segment.id,
+                        block: Some(size.disk),
+                        offset,
+                        length: header.length,
+                    },
+                };
+                moved.push((header.key, location));
+            } else if header.length != TOMBSTONE_LENGTH {
+                continue;
+            }
+            block.extend_from_slice(&header.encode());
+            block.extend_from_slice(&data);
+            if block.len() >= BLOCK_SIZE {
+                size.raw += block.len() as u64;
+                size.disk += write_records(&mut writer, compression, &block)?;
+                block.clear();
+            }
+        }
+        if !block.is_empty() {
+            size.raw += block.len() as u64;
+            size.disk += write_records(&mut writer, compression, &block)?;
+        }
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&temp_path, segment_path(&self.path, rewritten))?;
+        if rewritten != segment {
+            fs::remove_file(segment_path(&self.path, segment))?;
+        }
+
+        self.segments[i] = rewritten;
+        self.offsets.extend(moved);
+        self.sizes.insert(segment.id, size);
+        // Active segment file is replaced, so new records have to be appended to the new one.
+        if i == self.segments.len() - 1 {
+            self.active =
+                OpenOptions::new().append(true).open(segment_path(&self.path, rewritten))?;
+            self.active_length = size.raw;
+        }
+        Ok(size)
     }
 }
 
 impl _Storage for File {
-    fn write(&mut self, _key: Key, _data: &[u8]) -> Result<(), Error> {
-        todo!()
+    fn write(&mut self, key: Key, data: &[u8]) -> Result<(), Error> {
+        let length = u32::try_from(data.len())
+            .ok()
+            .filter(|x| *x != TOMBSTONE_LENGTH)
+            .ok_or(Error::TooLarge(data.len()))?;
+        let header = Header {
+            key,
+            length,
+            checksum: checksum(key, data),
+        };
+        let location = self.append(header, data)?;
+        if let Some(old) = self.offsets.insert(key, location) {
+            self.unlink(old);
+        }
+        *self.live.entry(location.segment).or_default() += 1;
+        self.bytes += length as u64;
+        Ok(())
+    }
+
+    fn read(&self, key: Key) -> Result<Vec<u8>, Error> {
+        let location = self.offsets.get(&key).ok_or(Error::NotFound)?;
+        let segment =
+            self.segments.iter().find(|x| x.id == location.segment).ok_or_else(|| {
+                Error::Corrupted(format!("segment is missing: {}", location.segment))
+            })?;
+        let mut file = fs::File::open(segment_path(&self.path, *segment))?;
+        let start = location.offset as usize;
+        let end = start + location.length as usize;
+        match location.block {
+            Some(block) => {
+                file.seek(SeekFrom::Start(block))?;
+                let data = read_block(&mut BufReader::new(file), segment.compression)?
+                    .ok_or_else(|| Error::Corrupted("block is missing".to_string()))?;
+                data.get(start..end)
+                    .map(|x| x.to_vec())
+                    .ok_or_else(|| Error::Corrupted("record is out of the block".to_string()))
+            }
+            None => {
+                file.seek(SeekFrom::Start(location.offset))?;
+                let mut data: Vec<u8> = vec![0; location.length as usize];
+                file.read_exact(&mut data)?;
+                Ok(data)
+            }
+        }
+    }
+
+    fn delete(&mut self, key: Key) -> Result<(), Error> {
+        let location = *self.offsets.get(&key).ok_or(Error::NotFound)?;
+        let header = Header {
+            key,
+            length: TOMBSTONE_LENGTH,
+            checksum: checksum(key, &[]),
+        };
+        self.append(header, &[])?;
+        self.offsets.remove(&key);
+        self.unlink(location);
+        self.remove_empty_segments()
+    }
+
+    fn purge(&mut self) -> Result<(), Error> {
+        for i in 0..self.segments.len() {
+            let segment = self.segments[i];
+            if !self.garbage.remove(&segment.id) {
+                continue;
+            }
+            let size = self.rewrite_segment(i, segment.compression)?;
+            debug!(segment = segment.id, disk = size.disk, "deleted data is purged from segment");
+        }
+        Ok(())
+    }
+
+    fn list(&self) -> Result<ListResult<'_>, Error> {
+        Ok(Box::new(SegmentsIterator {
+            storage: self,
+            segments: self.segments.iter(),
+            current: None,
+        }))
+    }
+
+    fn keys(&self) -> KeysResult<'_> {
+        Box::new(self.offsets.keys().copied())
+    }
+
+    fn first(&self) -> Option<Key> {
+        self.offsets.keys().next().copied()
     }
 
-    fn read(&self, _key: Key) -> Result<Vec<u8>, Error> {
-        todo!()
+    fn stats(&self) -> Stats {
+        let mut stats = Stats {
+            records: self.offsets.len() as u64,
+            bytes: self.bytes,
+            raw_bytes: self.active_length,
+            disk_bytes: self.active_length,
+        };
+        for segment in &self.segments[..self.segments.len() - 1] {
+            let size = self.sizes.get(&segment.id).copied().unwrap_or_default();
+            stats.raw_bytes += size.raw;
+            stats.disk_bytes += size.disk;
+        }
+        stats
     }
+}
+
+/// Reads segments one by one, so we don't need to keep all records in memory.
+struct SegmentsIterator<'a> {
+    storage: &'a File,
+    segments: std::slice::Iter<'a, Segment>,
+    current: Option<SegmentReader>,
+}
+
+impl<'a> Iterator for SegmentsIterator<'a> {
+    type Item = Result<(Key, Vec<u8>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let reader = match &mut self.current {
+                Some(reader) => reader,
+                None => {
+                    let segment = *self.segments.next()?;
+                    match SegmentReader::open(&self.storage.path, segment) {
+                        Ok(reader) => self.current.insert(reader),
+                        Err(e) => return Some(Err(e)),
+                    }
+                }
+            };
+            let (location, header, data) = match reader.next_record() {
+                Ok(Some(record)) => record,
+                Ok(None) => {
+                    self.current = None;
+                    continue;
+                }
+                Err(e) => return Some(Err(e)),
+            };
+            // Skip records which were overwritten later by the same key.
+            if self.storage.offsets.get(&header.key) != Some(&location) {
+                continue;
+            }
+            return Some(Ok((header.key, data)));
+        }
+    }
+}
 
-    fn list(&self) -> Result<Vec<(Key, Vec<u8>)>, Error> {
-        todo!()
+/// Reads records of raw and compressed segments one by one.
+struct SegmentReader {
+    segment: Segment,
+    reader: BufReader<fs::File>,
+    // Offset of the next record in the raw segment or of the next block in the compressed one.
+    offset: u64,
+    // Decompressed block with its offset in the segment.
+    block: Option<(u64, Cursor<Vec<u8>>)>,
+    // Size of all read records.
+    raw_length: u64,
+}
+
+impl SegmentReader {
+    fn open(path: &Path, segment: Segment) -> Result<Self, Error> {
+        Ok(Self {
+            segment,
+            reader: BufReader::new(fs::File::open(segment_path(path, segment))?),
+            offset: 0,
+            block: None,
+            raw_length: 0,
+        })
+    }
+
+    /// Returns None if there are no more records in the segment.
+    fn next_record(&mut self) -> Result<Option<(Location, Header, Vec<u8>)>, Error> {
+        if self.segment.compression == CompressionType::None {
+            let Some((header, data)) = read_record(&mut self.reader)? else {
+                return Ok(None);
+            };
+            let location = Location {
+                segment: self.segment.id,
+                block: None,
+                offset: self.offset + HEADER_LENGTH as u64,
+                length: header.length,
+            };
+            self.offset += (HEADER_LENGTH + data.len()) as u64;
+            self.raw_length = self.offset;
+            return Ok(Some((location, header, data)));
+        }
+        loop {
+            if let Some((block_offset, block)) = &mut self.block {
+                let offset = block.position();
+                if let Some((header, data)) = read_record(block)? {
+                    let location = Location {
+                        segment: self.segment.id,
+                        block: Some(*block_offset),
+                        offset: offset + HEADER_LENGTH as u64,
+                        length: header.length,
+                    };
+                    return Ok(Some((location, header, data)));
+                }
+            }
+            let Some(block) = read_block(&mut self.reader, self.segment.compression)? else {
+                return Ok(None);
+            };
+            let block_offset = self.offset;
+            self.offset = self.reader.stream_position()?;
+            self.raw_length += block.len() as u64;
+            self.block = Some((block_offset, Cursor::new(block)));
+        }
+    }
+}
+
+/// Reads the next record from the segment.
+/// Returns None if there are no more records in the segment.
+fn read_record(reader: &mut impl Read) -> Result<Option<(Header, Vec<u8>)>, Error> {
+    let mut buf = [0; HEADER_LENGTH];
+    let n = read_full(reader, &mut buf)?;
+    if n == 0 {
+        return Ok(None);
+    }
+    if n != HEADER_LENGTH {
+        return Err(Error::Corrupted("record header is incomplete".to_string()));
+    }
+    let header = Header::decode(&buf);
+    if header.length == TOMBSTONE_LENGTH {
+        if checksum(header.key, &[]) != header.checksum {
+            return Err(Error::Corrupted("tombstone checksum mismatch".to_string()));
+        }
+        return Ok(Some((header, Vec::new())));
+    }
+    // We don't preallocate buffer by the length from the header
+    // as it can be garbage in case of torn write.
+    let mut data: Vec<u8> = Vec::new();
+    reader.by_ref().take(header.length as u64).read_to_end(&mut data)?;
+    if data.len() != header.length as usize {
+        return Err(Error::Corrupted("record data is incomplete".to_string()));
+    }
+    if checksum(header.key, &data) != header.checksum {
+        return Err(Error::Corrupted("record checksum mismatch".to_string()));
+    }
+    Ok(Some((header, data)))
+}
+
+/// Reads and decompresses the next block from the compressed segment.
+/// Returns None if there are no more blocks in the segment.
+fn read_block(
+    reader: &mut impl Read,
+    compression: CompressionType,
+) -> Result<Option<Vec<u8>>, Error> {
+    let mut buf = [0; BLOCK_HEADER_LENGTH];
+    let n = read_full(reader, &mut buf)?;
+    if n == 0 {
+        return Ok(None);
+    }
+    if n != BLOCK_HEADER_LENGTH {
+        return Err(Error::Corrupted("block header is incomplete".to_string()));
+    }
+    let raw_length = u32::from_le_bytes(buf[0..4].try_into().expect("slice has correct length"));
+    let length = u32::from_le_bytes(buf[4..8].try_into().expect("slice has correct length"));
+    let block_checksum =
+        u32::from_le_bytes(buf[8..12].try_into().expect("slice has correct length"));
+    let mut data: Vec<u8> = Vec::new();
+    reader.by_ref().take(length as u64).read_to_end(&mut data)?;
+    if data.len() != length as usize {
+        return Err(Error::Corrupted("block data is incomplete".to_string()));
+    }
+    if crc32fast::hash(&data) != block_checksum {
+        return Err(Error::Corrupted("block checksum mismatch".to_string()));
+    }
+    let data = decompress(compression, &data, raw_length as usize)?;
+    if data.len() != raw_length as usize {
+        return Err(Error::Corrupted("decompressed block length mismatch".to_string()));
     }
+    Ok(Some(data))
+}
+
+// Writes records as is to the raw segment or in a block to the compressed one.
+// Returns the number of written bytes.
+fn write_records(
+    writer: &mut impl Write,
+    compression: CompressionType,
+    records: &[u8],
+) -> Result<u64, Error> {
+    if compression == CompressionType::None {
+        writer.write_all(records)?;
+        return Ok(records.len() as u64);
+    }
+    write_block(writer, compression, records)
+}
+
+// Returns the number of written bytes.
+fn write_block(
+    writer: &mut impl Write,
+    compression: CompressionType,
+    block: &[u8],
+) -> Result<u64, Error> {
+    let data = compress(compression, block)?;
+    let mut header = [0; BLOCK_HEADER_LENGTH];
+    header[0..4].copy_from_slice(&(block.len() as u32).to_le_bytes());
+    header[4..8].copy_from_slice(&(data.len() as u32).to_le_bytes());
+    header[8..12].copy_from_slice(&crc32fast::hash(&data).to_le_bytes());
+    writer.write_all(&header)?;
+    writer.write_all(&data)?;
+    Ok((BLOCK_HEADER_LENGTH + data.len()) as u64)
+}
+
+fn compress(compression: CompressionType, data: &[u8]) -> Result<Vec<u8>, Error> {
+    match compression {
+        CompressionType::Zstd => {
+            zstd::bulk::compress(data, ZSTD_LEVEL).map_err(|e| Error::Compression(e.to_string()))
+        }
+        CompressionType::Lz4 => Ok(lz4_flex::block::compress(data)),
+        _ => Err(Error::Compression(format!("can't compress by {}", compression))),
+    }
+}
+
+fn decompress(compression: CompressionType, data: &[u8], length: usize) -> Result<Vec<u8>, Error> {
+    match compression {
+        CompressionType::Zstd => {
+            zstd::bulk::decompress(data, length).map_err(|e| Error::Compression(e.to_string()))
+        }
+        CompressionType::Lz4 => {
+            lz4_flex::block::decompress(data, length).map_err(|e| Error::Compression(e.to_string()))
+        }
+        _ => Err(Error::Compression(format!("can't decompress by {}", compression))),
+    }
+}
+
+/// Works like read_exact but returns the number of read bytes instead of error on EOF.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut n = 0;
+    while n < buf.len() {
+        match reader.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(read) => n += read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(n)
+}
+
+/// Fills offsets table with records from the segment and removes deleted ones.
+/// Torn record at the end of the last segment is truncated
+/// as it is the result of the crash in the middle of the write.
+/// Returns the size of the segment records.
+fn load_segment(
+    path: &Path,
+    segment: Segment,
+    is_last: bool,
+    offsets: &mut BTreeMap<Key, Location>,
+) -> Result<u64, Error> {
+    let mut reader = SegmentReader::open(path, segment)?;
+    loop {
+        let offset = reader.offset;
+        match reader.next_record() {
+            Ok(Some((_, header, _))) if header.length == TOMBSTONE_LENGTH => {
+                offsets.remove(&header.key);
+            }
+            Ok(Some((location, header, _))) => {
+                offsets.insert(header.key, location);
+            }
+            Ok(None) => return Ok(reader.raw_length),
+            Err(Error::Corrupted(e)) if is_last && segment.compression == CompressionType::None => {
+                warn!(
+                    segment = segment.id,
+                    offset, "truncating torn write at the end of the segment: {}", e
+                );
+                OpenOptions::new()
+                    .write(true)
+                    .open(segment_path(path, segment))?
+                    .set_len(offset)?;
+                return Ok(offset);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn list_segments(path: &Path) -> Result<Vec<Segment>, Error> {
+    let mut segments: BTreeMap<SegmentId, Segment> = BTreeMap::new();
+    for entry in fs::read_dir(path)? {
+        let entry_path = entry?.path();
+        let compression = match entry_path.extension().and_then(|x| x.to_str()) {
+            Some(SEGMENT_EXTENSION) => CompressionType::None,
+            Some(ZSTD_SEGMENT_EXTENSION) => CompressionType::Zstd,
+            Some(LZ4_SEGMENT_EXTENSION) => CompressionType::Lz4,
+            // Segment rewrite was interrupted, the original segment is still there.
+            Some(TEMP_EXTENSION) => {
+                fs::remove_file(&entry_path)?;
+                continue;
+            }
+            _ => continue,
+        };
+        let id = entry_path
+            .file_stem()
+            .and_then(|x| x.to_str())
+            .and_then(|x| x.parse::<SegmentId>().ok())
+            .ok_or_else(|| Error::Corrupted(format!("invalid segment name: {:?}", entry_path)))?;
+        let segment = Segment { id, compression };
+        // Raw segment is removed after the compressed one is written,
+        // so we remove it if the crash happened between them.
+        match segments.insert(id, segment) {
+            Some(raw) if raw.compression == CompressionType::None => {
+                fs::remove_file(segment_path(path, raw))?
+            }
+            Some(compressed) if segment.compression == CompressionType::None => {
+                fs::remove_file(segment_path(path, segment))?;
+                segments.insert(id, compressed);
+            }
+            Some(_) => return Err(Error::Corrupted(format!("duplicated segment: {}", id))),
+            None => (),
+        }
+    }
+    Ok(segments.into_values().collect())
+}
+
+fn segment_path(path: &Path, segment: Segment) -> PathBuf {
+    let extension = match segment.compression {
+        CompressionType::Zstd => ZSTD_SEGMENT_EXTENSION,
+        CompressionType::Lz4 => LZ4_SEGMENT_EXTENSION,
+        _ => SEGMENT_EXTENSION,
+    };
+    path.join(format!("{:020}.{}", segment.id, extension))
+}
+
+fn checksum(key: Key, data: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&key.to_le_bytes());
+    hasher.update(data);
+    hasher.finalize()
 }
@@ -1,24 +1,32 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
-use crate::{log_storage::Key, storage::_Storage};
+use crate::{
+    log_storage::Key,
+    storage::{KeysResult, ListResult, Stats, _Storage},
+};
 
 use super::error::Error;
 
 pub(super) struct InMemory {
-    values: HashMap<Key, Vec<u8>>,
+    values: BTreeMap<Key, Vec<u8>>,
+    bytes: u64,
 }
 
 impl InMemory {
     pub(super) fn new() -> Self {
         Self {
-            values: HashMap::new(),
+            values: BTreeMap::new(),
+            bytes: 0,
         }
     }
 }
 
 impl _Storage for InMemory {
     fn write(&mut self, key: Key, data: &[u8]) -> Result<(), Error> {
-        self.values.insert(key, data.to_vec());
+        self.bytes += data.len() as u64;
+        if let Some(old) = self.values.insert(key, data.to_vec()) {
+            self.bytes -= old.len() as u64;
+        }
         Ok(())
     }
 
@@ -26,7 +34,34 @@ impl _Storage for InMemory {
         Ok(self.values.get(&key).ok_or(Error::NotFound)?.clone())
     }
 
-    fn list(&self) -> Result<Vec<(Key, Vec<u8>)>, Error> {
-        Ok(self.values.iter().map(|x| (*x.0, x.1.clone())).collect())
+    fn delete(&mut self, key: Key) -> Result<(), Error> {
+        let data = self.values.remove(&key).ok_or(Error::NotFound)?;
+        self.bytes -= data.len() as u64;
+        Ok(())
+    }
+
+    fn purge(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn list(&self) -> Result<ListResult<'_>, Error> {
+        Ok(Box::new(self.values.iter().map(|x| Ok((*x.0, x.1.clone())))))
+    }
+
+    fn keys(&self) -> KeysResult<'_> {
+        Box::new(self.values.keys().copied())
+    }
+
+    fn first(&self) -> Option<Key> {
+        self.values.keys().next().copied()
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            records: self.values.len() as u64,
+            bytes: self.bytes,
+            raw_bytes: self.bytes,
+            disk_bytes: self.bytes,
+        }
     }
 }
@@ -1,28 +1,62 @@
 use tracing::info;
 
+use compression_type::CompressionType;
 use error::Error;
 use storage_type::StorageType;
 
 use crate::log_storage::Key;
 
-mod error;
+mod compression_type;
+pub(crate) mod error;
 mod file;
 mod in_memory;
 mod storage_type;
 
 pub(crate) type Storage = Box<dyn _Storage + Send + Sync>;
 
+pub(crate) type ListResult<'a> = Box<dyn Iterator<Item = Result<(Key, Vec<u8>), Error>> + 'a>;
+pub(crate) type KeysResult<'a> = Box<dyn Iterator<Item = Key> + 'a>;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Stats {
+    pub(crate) records: u64,
+    // Size of stored records without storage overhead.
+    pub(crate) bytes: u64,
+    // Size of stored data with storage overhead before and after compression.
+    pub(crate) raw_bytes: u64,
+    pub(crate) disk_bytes: u64,
+}
+
 pub(crate) trait _Storage {
     fn write(&mut self, key: Key, data: &[u8]) -> Result<(), Error>;
     fn read(&self, key: Key) -> Result<Vec<u8>, Error>;
-    fn list(&self) -> Result<Vec<(Key, Vec<u8>)>, Error>;
+    fn delete(&mut self, key: Key) -> Result<(), Error>;
+    // Removes data of deleted records from the disk, it can be kept there until this call.
+    fn purge(&mut self) -> Result<(), Error>;
+    fn list(&self) -> Result<ListResult<'_>, Error>;
+    // Returns keys of all records without reading their data.
+    fn keys(&self) -> KeysResult<'_>;
+    // Returns the smallest key, which is the oldest record as keys grow with time.
+    fn first(&self) -> Option<Key>;
+    fn stats(&self) -> Stats;
 }
 
-pub(super) fn new_storage(storage_name: &str) -> Result<Storage, Error> {
+pub(super) fn new_storage(
+    storage_name: &str,
+    storage_path: &str,
+    segment_size: u64,
+    compression: &str,
+) -> Result<Storage, Error> {
+    let compression_type: CompressionType = compression.into();
+    if let CompressionType::Unknown = compression_type {
+        return Err(Error::UnknownCompressionType(compression.to_string()));
+    }
     let storage_type: storage_type::StorageType = storage_name.into();
     let storage: Storage = match storage_type {
         StorageType::InMemory => Box::new(in_memory::InMemory::new()),
-        StorageType::File => Box::new(file::File::new()?),
+        StorageType::File => {
+            Box::new(file::File::new(storage_path, segment_size, compression_type)?)
+        }
         StorageType::Unknown => return Err(Error::UnknownStorageType(storage_name.to_string())),
     };
     info!(storage_type = &storage_type.to_string(), "using as a storage");
@@ -31,17 +65,196 @@ pub(super) fn new_storage(storage_name: &str) -> Result<Storage, Error> {
 
 #[cfg(test)]
 mod tests {
+    use std::io::Write;
+
     use super::*;
 
     #[test]
     fn test_in_memory() {
-        let storage = new_storage(&storage_type::StorageType::InMemory.to_string()).unwrap();
+        let storage = new_storage(&StorageType::InMemory.to_string(), "", 0, "none").unwrap();
         test_storage(storage)
     }
 
-    #[ignore = "file storage is not implemented"]
     #[test]
-    fn test_file() {}
+    fn test_file() {
+        let path = temp_dir();
+        let storage = new_file_storage(&path, 1024);
+        test_storage(storage);
+        // Reopen storage to check that records are restored from segments.
+        let storage = new_file_storage(&path, 1024);
+        assert_eq!(storage.read(3).unwrap(), "asd3".as_bytes());
+        assert_eq!(storage.list().unwrap().count(), 4);
+        assert_eq!((4, 16), records_stats(&storage));
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_file_segments() {
+        let path = temp_dir();
+        // Every record (16 bytes header + 4 bytes data) takes a separate segment.
+        let mut storage = new_file_storage(&path, 30);
+        for key in 1..=5 {
+            storage.write(key, format!("asd{}", key).as_bytes()).unwrap();
+        }
+        assert_eq!(std::fs::read_dir(&path).unwrap().count(), 5);
+        let storage = new_file_storage(&path, 30);
+        let keys: Vec<Key> = storage.list().unwrap().map(|x| x.unwrap().0).collect();
+        assert_eq!(keys, vec![1, 2, 3, 4, 5]);
+        assert_eq!(storage.read(5).unwrap(), "asd5".as_bytes());
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_file_delete() {
+        let path = temp_dir();
+        let mut storage = new_file_storage(&path, 30);
+        for key in 1..=5 {
+            storage.write(key, format!("asd{}", key).as_bytes()).unwrap();
+        }
+        storage.delete(1).unwrap();
+        storage.delete(2).unwrap();
+        storage.delete(4).unwrap();
+        // Segments of the first records are removed, but the segment in the middle is kept.
+        let segments = std::fs::read_dir(&path).unwrap().count();
+        let storage = new_file_storage(&path, 30);
+        assert_eq!(std::fs::read_dir(&path).unwrap().count(), segments);
+        let keys: Vec<Key> = storage.list().unwrap().map(|x| x.unwrap().0).collect();
+        assert_eq!(keys, vec![3, 5]);
+        assert_eq!(storage.first(), Some(3));
+        assert_eq!((2, 8), records_stats(&storage));
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_file_purge() {
+        for compression in [CompressionType::None, CompressionType::Zstd] {
+            let path = temp_dir();
+            let mut storage = new_compressed_file_storage(&path, 100, compression);
+            for key in 1..=10 {
+                storage.write(key, format!("secret{}", key).as_bytes()).unwrap();
+            }
+            storage.write(3, b"public3").unwrap();
+            // Records are deleted from sealed and active segments.
+            for key in [2, 5, 10] {
+                storage.delete(key).unwrap();
+            }
+            storage.purge().unwrap();
+            storage.write(11, b"secret11").unwrap();
+            for entry in std::fs::read_dir(&path).unwrap() {
+                let data = std::fs::read(entry.unwrap().path()).unwrap();
+                for secret in ["secret2", "secret3", "secret5", "secret10"] {
+                    let found = data.windows(secret.len()).any(|x| x == secret.as_bytes());
+                    assert!(!found, "{}: {} is found", compression, secret);
+                }
+            }
+
+            let storage = new_compressed_file_storage(&path, 100, compression);
+            assert_eq!(storage.list().unwrap().count(), 8);
+            let keys: Vec<Key> = storage.keys().collect();
+            assert_eq!(keys, vec![1, 3, 4, 6, 7, 8, 9, 11]);
+            assert_eq!(storage.read(3).unwrap(), b"public3");
+            assert_eq!(storage.read(11).unwrap(), b"secret11");
+            std::fs::remove_dir_all(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_file_torn_write() {
+        let path = temp_dir();
+        let mut storage = new_file_storage(&path, 1024);
+        storage.write(1, "asd1".as_bytes()).unwrap();
+        storage.write(2, "asd2".as_bytes()).unwrap();
+        drop(storage);
+        // Simulate crash in the middle of the write.
+        let segment = std::fs::read_dir(&path).unwrap().next().unwrap().unwrap().path();
+        let mut file = std::fs::OpenOptions::new().append(true).open(&segment).unwrap();
+        file.write_all(&[3, 0, 0, 0, 0, 0, 0, 0, 100, 0]).unwrap();
+        drop(file);
+
+        let mut storage = new_file_storage(&path, 1024);
+        assert_eq!(storage.list().unwrap().count(), 2);
+        storage.write(3, "asd3".as_bytes()).unwrap();
+        let storage = new_file_storage(&path, 1024);
+        let values: Vec<(Key, Vec<u8>)> = storage.list().unwrap().map(|x| x.unwrap()).collect();
+        assert_eq!(values.len(), 3);
+        assert_eq!(values[2], (3, "asd3".as_bytes().to_vec()));
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    fn new_file_storage(path: &str, segment_size: u64) -> Storage {
+        new_compressed_file_storage(path, segment_size, CompressionType::None)
+    }
+
+    fn new_compressed_file_storage(
+        path: &str,
+        segment_size: u64,
+        compression: CompressionType,
+    ) -> Storage {
+        let storage_type = StorageType::File.to_string();
+        new_storage(&storage_type, path, segment_size, &compression.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_file_compression() {
+        for compression in [CompressionType::Zstd, CompressionType::Lz4] {
+            let path = temp_dir();
+            // Start with raw segments to check that they are compressed on load.
+            let mut storage = new_file_storage(&path, 1024);
+            let data = |key: Key| format!(r#"{{"level":"debug","message":"log {}"}}"#, key);
+            for key in 1..=100 {
+                storage.write(key, data(key).as_bytes()).unwrap();
+            }
+            assert_eq!(storage.stats().raw_bytes, storage.stats().disk_bytes);
+            drop(storage);
+
+            let mut storage = new_compressed_file_storage(&path, 1024, compression);
+            for key in 101..=200 {
+                storage.write(key, data(key).as_bytes()).unwrap();
+            }
+            storage.write(150, b"overwritten").unwrap();
+            storage.delete(1).unwrap();
+            storage.delete(120).unwrap();
+            let stats = storage.stats();
+            assert!(stats.raw_bytes > stats.disk_bytes * 2, "{}: {:?}", compression, stats);
+            let extension = if compression == CompressionType::Zstd {
+                "zst"
+            } else {
+                "lz4"
+            };
+            let compressed = std::fs::read_dir(&path)
+                .unwrap()
+                .filter(|x| x.as_ref().unwrap().path().extension().unwrap() == extension)
+                .count();
+            assert!(compressed > 1);
+
+            // Compressed segments are read regardless of the configured compression.
+            for storage in [
+                storage,
+                new_compressed_file_storage(&path, 1024, compression),
+                new_file_storage(&path, 1024),
+            ] {
+                assert_eq!(storage.read(2).unwrap(), data(2).as_bytes());
+                assert_eq!(storage.read(150).unwrap(), b"overwritten");
+                assert!(matches!(storage.read(120), Err(Error::NotFound)));
+                let values: Vec<(Key, Vec<u8>)> =
+                    storage.list().unwrap().map(|x| x.unwrap()).collect();
+                assert_eq!(values.len(), 198);
+                assert_eq!(values[0], (2, data(2).as_bytes().to_vec()));
+                assert_eq!(storage.first(), Some(2));
+            }
+            std::fs::remove_dir_all(path).unwrap();
+        }
+    }
+
+    fn records_stats(storage: &Storage) -> (u64, u64) {
+        let stats = storage.stats();
+        (stats.records, stats.bytes)
+    }
+
+    fn temp_dir() -> String {
+        let path = std::env::temp_dir().join(format!("loghell-test-{}", fastrand::u64(..)));
+        path.to_str().unwrap().to_string()
+    }
 
     fn test_storage(mut storage: Storage) {
         let key1 = 1;
@@ -64,6 +277,17 @@ mod tests {
         assert_eq!(storage.read(key4).unwrap(), data4);
 
         let values = storage.list().unwrap();
-        assert_eq!(values.len(), 4);
+        assert_eq!(values.count(), 4);
+        let mut keys: Vec<Key> = storage.keys().collect();
+        keys.sort();
+        assert_eq!(keys, vec![key1, key2, key3, key4]);
+
+        assert_eq!(storage.first(), Some(key1));
+        storage.delete(key1).unwrap();
+        assert!(matches!(storage.read(key1), Err(Error::NotFound)));
+        assert!(matches!(storage.delete(key1), Err(Error::NotFound)));
+        assert_eq!(storage.first(), Some(key2));
+        assert_eq!((3, 12), records_stats(&storage));
+        storage.write(key1, data1).unwrap();
     }
 }
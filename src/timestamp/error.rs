@@ -0,0 +1,7 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub(crate) enum Error {
+    #[error("unknown timestamp format: {0}")]
+    UnknownFormat(String),
+}
@@ -0,0 +1,41 @@
+use std::fmt::{Display, Formatter};
+
+const UNKNOWN: &str = "unknown";
+const RFC3339: &str = "rfc3339";
+const UNIX_SECONDS: &str = "unix_s";
+const UNIX_MILLIS: &str = "unix_ms";
+const UNIX_NANOS: &str = "unix_ns";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(super) enum FormatType {
+    Unknown,
+    Rfc3339,
+    UnixSeconds,
+    UnixMillis,
+    UnixNanos,
+}
+
+impl Display for FormatType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            FormatType::Unknown => UNKNOWN.to_string(),
+            FormatType::Rfc3339 => RFC3339.to_string(),
+            FormatType::UnixSeconds => UNIX_SECONDS.to_string(),
+            FormatType::UnixMillis => UNIX_MILLIS.to_string(),
+            FormatType::UnixNanos => UNIX_NANOS.to_string(),
+        };
+        write!(f, "{}", str)
+    }
+}
+
+impl From<&str> for FormatType {
+    fn from(str: &str) -> Self {
+        match str {
+            RFC3339 => FormatType::Rfc3339,
+            UNIX_SECONDS => FormatType::UnixSeconds,
+            UNIX_MILLIS => FormatType::UnixMillis,
+            UNIX_NANOS => FormatType::UnixNanos,
+            _ => FormatType::Unknown,
+        }
+    }
+}
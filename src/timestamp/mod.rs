@@ -0,0 +1,118 @@
+use error::Error;
+use format_type::FormatType;
+
+pub(crate) mod error;
+mod format_type;
+mod rfc3339;
+
+/// Extracts event time from the log field,
+/// so logs are searched by the time they happened instead of the time they were received.
+pub(crate) struct TimestampExtractor {
+    // Path to the field, nested fields are separated by dots.
+    path: Vec<String>,
+    formats: Vec<FormatType>,
+}
+
+impl TimestampExtractor {
+    /// Formats are separated by commas and are tried in the given order.
+    /// Empty field disables extraction.
+    pub(crate) fn new(field: &str, formats: &str) -> Result<Self, Error> {
+        let path = match field {
+            "" => Vec::new(),
+            field => field.split('.').map(str::to_string).collect(),
+        };
+        let mut format_types: Vec<FormatType> = Vec::new();
+        for format in formats.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            let format_type = FormatType::from(format);
+            if format_type == FormatType::Unknown {
+                return Err(Error::UnknownFormat(format.to_string()));
+            }
+            format_types.push(format_type);
+        }
+        Ok(Self {
+            path,
+            formats: format_types,
+        })
+    }
+
+    /// Returns unix time in nanoseconds.
+    /// Returns None if there is no such field or it can't be parsed by any format.
+    pub(crate) fn extract(&self, data: &[u8]) -> Option<u64> {
+        if self.path.is_empty() {
+            return None;
+        }
+        let value: serde_json::Value = serde_json::from_slice(data).ok()?;
+        let value = self.path.iter().try_fold(&value, |value, name| value.get(name))?;
+        self.formats.iter().find_map(|format| parse(value, *format))
+    }
+}
+
+fn parse(value: &serde_json::Value, format: FormatType) -> Option<u64> {
+    let multiplier: f64 = match format {
+        FormatType::Rfc3339 => return rfc3339::parse(value.as_str()?),
+        FormatType::UnixSeconds => 1_000_000_000.0,
+        FormatType::UnixMillis => 1_000_000.0,
+        FormatType::UnixNanos => 1.0,
+        FormatType::Unknown => return None,
+    };
+    let number = match value {
+        serde_json::Value::Number(number) => number.clone(),
+        serde_json::Value::String(value) => value.parse::<serde_json::Number>().ok()?,
+        _ => return None,
+    };
+    // Integers are converted without floats to not lose nanoseconds precision.
+    if let Some(number) = number.as_u64() {
+        return number.checked_mul(multiplier as u64);
+    }
+    let nanos = number.as_f64()? * multiplier;
+    if nanos < 0.0 || nanos >= u64::MAX as f64 {
+        return None;
+    }
+    Some(nanos as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timestamp::*;
+
+    // 2023-05-20T18:37:22.880484Z
+    const NANOS: u64 = 1_684_607_842_880_484_000;
+
+    #[test]
+    fn test_extract() {
+        let extractor = TimestampExtractor::new("time", "rfc3339, unix_ms").unwrap();
+        let cases: [(&str, Option<u64>); 13] = [
+            (r#"{"time":"2023-05-20T18:37:22.880484Z"}"#, Some(NANOS)),
+            (r#"{"time":"2023-05-20t21:37:22.880484+03:00"}"#, Some(NANOS)),
+            (r#"{"time":"2023-05-20 18:07:22.880484-00:30"}"#, Some(NANOS)),
+            (r#"{"time":"2023-05-20T18:37:22.8804841239Z"}"#, Some(NANOS + 123)),
+            (r#"{"time":"1970-01-01T00:00:00Z"}"#, Some(0)),
+            (r#"{"time":1684607842880}"#, Some(NANOS / 1_000_000 * 1_000_000)),
+            (r#"{"time":"1684607842880.484"}"#, Some(NANOS)),
+            (r#"{"time":"2023-02-29T00:00:00Z"}"#, None),
+            (r#"{"time":"1969-12-31T23:59:59Z"}"#, None),
+            // Multibyte chars are not split.
+            (r#"{"time":"2023-05-20T18:37:2€Z"}"#, None),
+            (r#"{"time":"2023-05-20T18:37:22€"}"#, None),
+            (r#"{"time":-1}"#, None),
+            (r#"{"ts":1684607842880}"#, None),
+        ];
+        for (data, expected) in cases {
+            let actual = extractor.extract(data.as_bytes());
+            // Float conversion can be not exact.
+            let diff = actual.zip(expected).map(|(a, b)| a.abs_diff(b));
+            assert!(actual == expected || diff < Some(1_000), "data: {}", data);
+        }
+
+        let extractor = TimestampExtractor::new("meta.ts", "unix_s,unix_ns").unwrap();
+        let data = format!(r#"{{"meta":{{"ts":"{}"}}}}"#, NANOS / 1_000_000_000);
+        assert_eq!(Some(NANOS / 1_000_000_000 * 1_000_000_000), extractor.extract(data.as_bytes()));
+        let extractor = TimestampExtractor::new("ts", "unix_ns").unwrap();
+        let data = format!(r#"{{"ts":"{}"}}"#, NANOS);
+        assert_eq!(Some(NANOS), extractor.extract(data.as_bytes()));
+
+        let extractor = TimestampExtractor::new("", "rfc3339").unwrap();
+        assert_eq!(None, extractor.extract(br#"{"":"2023-05-20T18:37:22Z"}"#));
+        assert!(TimestampExtractor::new("time", "iso").is_err());
+    }
+}
@@ -0,0 +1,101 @@
+/// Parses RFC 3339 date time to unix time in nanoseconds.
+/// Returns None for invalid values and for times before unix epoch.
+pub(super) fn parse(value: &str) -> Option<u64> {
+    let bytes = value.as_bytes();
+    // The shortest valid value is "1970-01-01T00:00:00Z".
+    if bytes.len() < 20
+        || bytes[4] != b'-'
+        || bytes[7] != b'-'
+        || !matches!(bytes[10], b'T' | b't' | b' ')
+        || bytes[13] != b':'
+        || bytes[16] != b':'
+    {
+        return None;
+    }
+    // Value is parsed by bytes, as multibyte chars can be at any position.
+    let year = number(&bytes[0..4])?;
+    let month = number(&bytes[5..7])?;
+    let day = number(&bytes[8..10])?;
+    let hour = number(&bytes[11..13])?;
+    let minute = number(&bytes[14..16])?;
+    let second = number(&bytes[17..19])?;
+    if !(1..=12).contains(&month)
+        || day == 0
+        || day > days_in_month(year, month)
+        || hour > 23
+        || minute > 59
+        // Leap second is allowed by RFC 3339.
+        || second > 60
+    {
+        return None;
+    }
+
+    let mut rest = &bytes[19..];
+    let mut nanos: i64 = 0;
+    if let Some(fraction) = rest.strip_prefix(b".") {
+        let length = fraction.iter().position(|x| !x.is_ascii_digit()).unwrap_or(fraction.len());
+        if length == 0 {
+            return None;
+        }
+        // We keep only nanoseconds precision.
+        for (i, digit) in fraction[..length].iter().take(9).enumerate() {
+            nanos += (digit - b'0') as i64 * 10_i64.pow(8 - i as u32);
+        }
+        rest = &fraction[length..];
+    }
+
+    let offset = match rest {
+        [b'Z' | b'z'] => 0,
+        [sign @ (b'+' | b'-'), _, _, b':', _, _] => {
+            let hours = number(&rest[1..3])?;
+            let minutes = number(&rest[4..6])?;
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            let offset = (hours * 60 + minutes) * 60;
+            if *sign == b'+' {
+                offset
+            } else {
+                -offset
+            }
+        }
+        _ => return None,
+    };
+
+    let seconds =
+        days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second - offset;
+    let nanos = seconds.checked_mul(1_000_000_000)?.checked_add(nanos)?;
+    u64::try_from(nanos).ok()
+}
+
+fn number(value: &[u8]) -> Option<i64> {
+    if !value.iter().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+    Some(value.iter().fold(0, |number, digit| number * 10 + (digit - b'0') as i64))
+}
+
+fn is_leap_year(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Returns the number of days since unix epoch.
+// Algorithm is taken from http://howardhinnant.github.io/date_algorithms.html.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = (month + 9) % 12;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}